@@ -0,0 +1,49 @@
+//! Passes the version of syntect resolved in `Cargo.lock` as `SYNTECT_VERSION`,
+//! highlighted code in the cache is keyed on it.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Value of `key = "value"` in a package of the lock file
+fn field<'a>(package: &'a str, key: &str) -> Option<&'a str> {
+    package.lines().find_map(|x| {
+        let value = x.strip_prefix(key)?.trim_start().strip_prefix('=')?;
+        Some(value.trim().trim_matches('"'))
+    })
+}
+
+// Version of syntect the package depends on. The dependency names the version
+// only if several versions are resolved.
+fn syntect_version(lock: &str, package_name: &str) -> Option<String> {
+    let packages: Vec<&str> = lock.split("[[package]]").skip(1).collect();
+    let package = packages
+        .iter()
+        .find(|x| field(x, "name") == Some(package_name))?;
+    let dependency = package
+        .lines()
+        .map(|x| x.trim().trim_end_matches(',').trim_matches('"'))
+        .find(|x| *x == "syntect" || x.starts_with("syntect "))?;
+    match dependency.split(' ').nth(1) {
+        Some(version) => Some(version.to_string()),
+        None => packages
+            .iter()
+            .find(|x| field(x, "name") == Some("syntect"))
+            .and_then(|x| field(x, "version"))
+            .map(|x| x.to_string()),
+    }
+}
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let lock_path = Path::new(&manifest_dir).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+    let version = fs::read_to_string(&lock_path)
+        .ok()
+        .and_then(|x| syntect_version(&x, &env::var("CARGO_PKG_NAME").unwrap()));
+    let version = version.unwrap_or_else(|| {
+        println!("cargo:warning=syntect version not found in Cargo.lock, highlighted code cached won't be invalidated by upgrading it");
+        "unknown".to_string()
+    });
+    println!("cargo:rustc-env=SYNTECT_VERSION={}", version);
+}
@@ -1,16 +1,20 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
 use crate::blog_clusters::TagHandle;
+use crate::shared::path_title;
 
 fn valid_date(year: i64, month: i64, day: i64) -> bool {
-    if year > 2200 || year < 2000 {
+    if !(2000..=2200).contains(&year) {
         return false;
     }
     let leap = (((year % 4) == 0) && (year % 100 != 0)) || ((year % 400) == 0);
-    return match month {
-        1 | 3 | 5 | 7 | 8 | 10 | 12 => day <= 31 && day >= 1,
-        4 | 6 | 9 | 11 => day <= 30 && day >= 1,
-        2 => (day <= if leap { 29 } else { 28 }) && (day >= 1),
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => (1..=31).contains(&day),
+        4 | 6 | 9 | 11 => (1..=30).contains(&day),
+        2 => (1..=if leap { 29 } else { 28 }).contains(&day),
         _ => false,
-    };
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -21,7 +25,10 @@ pub struct Blog {
     pub title: String,
     pub tags: Vec<TagHandle>,
     pub preview: String,
-    pub content: String, // reference to the blog content
+    pub content: String,                 // reference to the blog content
+    pub slug: String,                    // file name of the blog page without extension
+    pub extra: BTreeMap<String, String>, // metadata we don't know
+    pub source: PathBuf,                 // path of the markdown file
 }
 
 impl Blog {
@@ -33,20 +40,28 @@ impl Blog {
         tags: Vec<TagHandle>,
         preview: String,
         content: String,
-    ) -> Self {
-        // This isn't a program for others, I would use it myself so I will panic whenever possible
+    ) -> Option<Self> {
+        // Return None if the date is invalid
         if !valid_date(year, month, day) {
-            panic!("Blog's date is invalid!");
+            return None;
         }
-        Blog {
+        Some(Blog {
             year: year as u16,
             month: month as u16,
             day: day as u16,
-            title: title,
-            tags: tags,
-            preview: preview,
-            content: content,
-        }
+            slug: path_title(&title),
+            title,
+            tags,
+            preview,
+            content,
+            extra: BTreeMap::new(),
+            source: PathBuf::new(),
+        })
+    }
+
+    // Path of the blog page, relative to the output directory.
+    pub fn path(&self) -> String {
+        format!("{}.html", self.slug)
     }
 }
 
@@ -55,27 +70,27 @@ mod blog_tests {
     use super::*;
     #[test]
     fn test_valid_date() {
-        assert_eq!(true, valid_date(2000, 2, 29));
-        assert_eq!(true, valid_date(2004, 2, 29));
-        assert_eq!(true, valid_date(2019, 12, 15));
+        assert!(valid_date(2000, 2, 29));
+        assert!(valid_date(2004, 2, 29));
+        assert!(valid_date(2019, 12, 15));
 
-        assert_eq!(true, valid_date(2001, 2, 28));
-        assert_eq!(true, valid_date(2002, 2, 28));
-        assert_eq!(true, valid_date(2003, 2, 28));
+        assert!(valid_date(2001, 2, 28));
+        assert!(valid_date(2002, 2, 28));
+        assert!(valid_date(2003, 2, 28));
 
-        assert_eq!(true, valid_date(2003, 3, 31));
+        assert!(valid_date(2003, 3, 31));
     }
 
     #[test]
     fn test_invalid_date() {
-        assert_eq!(false, valid_date(2001, 2, 29));
-        assert_eq!(false, valid_date(2002, 2, 29));
-        assert_eq!(false, valid_date(2003, 2, 29));
+        assert!(!valid_date(2001, 2, 29));
+        assert!(!valid_date(2002, 2, 29));
+        assert!(!valid_date(2003, 2, 29));
 
-        assert_eq!(false, valid_date(2003, 4, 31));
+        assert!(!valid_date(2003, 4, 31));
 
-        assert_eq!(false, valid_date(1999, 1, 1));
-        assert_eq!(false, valid_date(2099, 0, 1));
-        assert_eq!(false, valid_date(2099, 1, 0));
+        assert!(!valid_date(1999, 1, 1));
+        assert!(!valid_date(2099, 0, 1));
+        assert!(!valid_date(2099, 1, 0));
     }
 }
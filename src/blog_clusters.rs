@@ -1,7 +1,10 @@
 use crate::blog::Blog;
+use crate::error::{Error, Location, Reporter};
+use crate::metadata::{parse_metadata, split_separator};
 use crate::shared::path_title;
 use crate::tag::Tag;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 use std::str;
 use std::string::String;
@@ -15,6 +18,17 @@ pub type TagHandle = usize;
 // Add tags
 // Insert blogs(after tags were added because tags in metadata of articles needs validation)
 
+// Line number of the tag name, given line index of the tag description.
+fn tags_name_line(tags_raw: &str, desc_index: usize) -> usize {
+    tags_raw
+        .lines()
+        .take(desc_index)
+        .enumerate()
+        .filter(|(_, x)| !x.trim().is_empty())
+        .last()
+        .map_or(1, |(i, _)| i + 1)
+}
+
 // Squash time to comparable format
 fn time_squash<T: Into<u64>>(year: T, month: T, day: T) -> u64 {
     year.into() * 1024 + month.into() * 64 + day.into()
@@ -68,11 +82,11 @@ impl BlogClusters {
     // tagname
     // description
     // ```
-    pub fn add_tags(&mut self, tags_raw: &str) {
+    pub fn add_tags(&mut self, tags_path: &Path, tags_raw: &str, reporter: &mut Reporter) {
         let mut name_found = false;
         let mut tag_name = String::new();
-        let lines: Vec<&str> = tags_raw.lines().map(|x| x.trim()).collect();
-        for line in lines {
+        let lines = tags_raw.lines().map(|x| x.trim()).enumerate();
+        for (i, line) in lines {
             if !line.is_empty() {
                 let line = line.to_string();
                 if name_found {
@@ -83,7 +97,11 @@ impl BlogClusters {
                         Some(tag_handle) => {
                             self.tag_blog_map.insert(tag_handle, Vec::new());
                         }
-                        None => panic!("Duplicate tag name found."),
+                        // Name is on the previous non-empty line
+                        None => reporter.report(Error::DuplicateTag(
+                            Location::new(tags_path, tags_name_line(tags_raw, i), 1),
+                            tag_name.clone(),
+                        )),
                     }
                 } else {
                     tag_name = line;
@@ -94,83 +112,39 @@ impl BlogClusters {
     }
 
     // Should call add_tags before calling this.
-    // blog_mds: blog filename and blog content in markdown with metadata
-    // PS: blog_name is used for checking if the title in the file is corresponding
-    pub fn add_blogs(&mut self, blog_mds: &[(String, String)]) {
+    // blog_mds: blog file path and blog content in markdown with metadata
+    // PS: blog file name is used for checking if the title in the file is
+    // corresponding
+    pub fn add_blogs(&mut self, blog_mds: &[(PathBuf, String)], reporter: &mut Reporter) {
         // Insert blogs to blog vector
-        for (blog_path_title, blog) in blog_mds {
-            let mut line_it = blog.lines();
-
-            // First line is title
-            let title = line_it.next().unwrap().trim();
-
-            // We need to ensure title in content is roughly the same as file
-            // name. The path_title is only used for validation, the title
-            // stored is unprocessed.
-            assert_eq!(
-                &path_title(title),
-                &path_title(blog_path_title),
-                "Incorrespondence between filename and title"
-            );
-
-            // Second line is time: `2000 / 9 / 27`
-            let time = line_it
-                .next()
-                .unwrap()
-                .split('/')
-                .map(|x| x.trim().parse().expect("Invalid time."))
-                .collect::<Vec<i64>>();
-
-            assert_eq!(3, time.len(), "Invalid time format.");
-
-            // Third line is tags: `aaa | bbb | ccc | ...`
-            let tag_names = line_it
-                .next()
-                .unwrap()
-                .split('|')
-                .map(|x| x.trim()) // permits arbitrary spaces between time and seperator
-                .collect::<Vec<&str>>();
-            let tag_handles = tag_names
-                .into_iter()
-                .map(|x| *self.get_tag_handle(x).expect("Invalid tag name."))
-                .collect::<Vec<TagHandle>>();
-
-            // Assme there is no "---" in article content.
-            // Emmm.... This is not a legit assumption, we can change it later
-            let mut parts = blog.split("---");
-
-            // Assume there always three parts:
-            // Meta Data
-            // ---
-            // Preview
-            // ---
-            // Content
-
-            // Just ignore the metadata, because we have parsed it.
-            parts.next().expect("Where is the meta data?");
-            // Wrapping white spaces in preview and content is legal.
-            // Get the preview part
-            let preview = parts.next().expect("Where is the preview?").trim();
-            // Get the content part
-            let content = parts.next().expect("Where is the content?").trim();
-
-            self.blogs.push(Blog::new(
-                time[0],
-                time[1],
-                time[2],
-                title.to_string(),
-                tag_handles.clone(),
-                preview.to_string(),
-                content.to_string(),
-            ));
+        for (blog_path, blog) in blog_mds {
+            if let Some(blog) = reporter.check(self.parse_blog(blog_path, blog)) {
+                self.blogs.extend(blog);
+            }
         }
+        self.sort_blogs();
+    }
 
-        // Sort blog vector by time, from new to old
+    // Drop blogs parsed from the given markdown files, e.g. to add them again
+    // after they changed
+    pub fn remove_blogs(&mut self, sources: &[PathBuf]) {
+        self.blogs.retain(|x| !sources.contains(&x.source));
+        self.sort_blogs();
+    }
+
+    fn sort_blogs(&mut self) {
+        // Sort blog vector by time, from new to old. Blogs of the same day by
+        // file, so the order doesn't depend on when they were added.
         self.blogs.sort_by(|a, b| {
-            time_squash(b.year, b.month, b.day).cmp(&time_squash(a.year, a.month, a.day))
+            time_squash(b.year, b.month, b.day)
+                .cmp(&time_squash(a.year, a.month, a.day))
+                .then_with(|| a.source.cmp(&b.source))
         });
 
         // Map tag_handle-blog_handle pair
+        for blog_handles in self.tag_blog_map.values_mut() {
+            blog_handles.clear();
+        }
         for (i, blog) in self.blogs.iter().enumerate() {
             // Gen handle of current blog(just the index)
             let blog_handle = i;
@@ -178,14 +152,102 @@ impl BlogClusters {
             for tag_handle in &blog.tags {
                 // We know this tag_handle is always valid
                 self.tag_blog_map
-                    .get_mut(&tag_handle)
+                    .get_mut(tag_handle)
                     .unwrap()
                     .push(blog_handle);
             }
         }
     }
 
-    fn get_tag_handle(&self, tag_name: &str) -> Option<&TagHandle> {
+    // Return None if the blog is a draft.
+    fn parse_blog(&self, blog_path: &Path, blog: &str) -> Result<Option<Blog>, Error> {
+        let (metadata, body) = parse_metadata(blog_path, blog)?;
+
+        // Drafts are not published
+        if metadata.draft {
+            return Ok(None);
+        }
+
+        // We need to ensure title in content is roughly the same as file
+        // name. The path_title is only used for validation, the title
+        // stored is unprocessed.
+        let file_name = blog_path
+            .file_stem()
+            .map(|x| x.to_string_lossy())
+            .unwrap_or_default();
+        if path_title(&metadata.title) != path_title(&file_name) {
+            return Err(Error::TitleMismatch(
+                Location::find(blog_path, blog, metadata.title_line, &metadata.title),
+                metadata.title,
+                file_name.to_string(),
+            ));
+        }
+
+        let tag_handles = metadata
+            .tags
+            .iter()
+            .map(|x| match self.get_tag_handle(x) {
+                Some(x) => Ok(*x),
+                None => Err(Error::UnknownTag(
+                    Location::find(blog_path, blog, metadata.tags_line, x),
+                    x.clone(),
+                )),
+            })
+            .collect::<Result<Vec<TagHandle>, Error>>()?;
+
+        // Assume there always two parts if no summary is given:
+        // Preview
+        // ---
+        // Content
+        // Otherwise the summary is the preview and the body is the content.
+        // Only the first separator counts, so content could contain
+        // horizontal rules.
+
+        // Wrapping white spaces in preview and content is legal.
+        let (preview, content) = match &metadata.summary {
+            Some(summary) => (summary.trim(), body.trim()),
+            None => {
+                let (preview, content) = split_separator(body).ok_or_else(|| {
+                    Error::Metadata(
+                        Location::file(blog_path),
+                        "Where is the content? Preview and content should be separated by \"---\"."
+                            .to_string(),
+                    )
+                })?;
+                (preview.trim(), content.trim())
+            }
+        };
+
+        // Pages of blogs with the same slug would overwrite each other
+        let slug_source = metadata.slug.as_deref().unwrap_or(&metadata.title);
+        let slug = path_title(slug_source);
+        if let Some(other) = self.blogs.iter().find(|x| x.slug == slug) {
+            return Err(Error::DuplicateSlug(
+                Location::find(blog_path, blog, metadata.slug_line, slug_source),
+                slug,
+                other.source.clone(),
+            ));
+        }
+
+        let date = format!("{}/{}/{}", metadata.year, metadata.month, metadata.day);
+        let date_line = metadata.date_line;
+        let mut blog = Blog::new(
+            metadata.year,
+            metadata.month,
+            metadata.day,
+            metadata.title,
+            tag_handles,
+            preview.to_string(),
+            content.to_string(),
+        )
+        .ok_or_else(|| Error::InvalidDate(Location::new(blog_path, date_line, 1), date))?;
+        blog.slug = slug;
+        blog.extra = metadata.extra;
+        blog.source = blog_path.to_path_buf();
+        Ok(Some(blog))
+    }
+
+    pub fn get_tag_handle(&self, tag_name: &str) -> Option<&TagHandle> {
         self.tag_map.get(tag_name)
     }
 
@@ -197,6 +259,15 @@ impl BlogClusters {
         &self.tags
     }
 
+    // Handles of blogs referencing the tag, from new to old
+    pub fn get_tag_blogs(&self, tag_handle: TagHandle) -> Option<&Vec<BlogHandle>> {
+        self.tag_blog_map.get(&tag_handle)
+    }
+
+    pub fn get_blog(&self, blog_handle: BlogHandle) -> Option<&Blog> {
+        self.blogs.get(blog_handle)
+    }
+
     pub fn get_blogs(&self) -> &Vec<Blog> {
         &self.blogs
     }
@@ -213,6 +284,7 @@ impl BlogClusters {
 #[cfg(test)]
 mod blog_cluster_tests {
     use super::*;
+    use std::path::PathBuf;
 
     #[test]
     fn test_time_squashing() {
@@ -222,8 +294,10 @@ mod blog_cluster_tests {
 
     #[test]
     fn test_tag_parsing() {
+        let mut reporter = Reporter::new();
         let mut clusters = BlogClusters::new();
         clusters.add_tags(
+            Path::new("tags.txt"),
             "life
             things about current life
 
@@ -238,28 +312,41 @@ mod blog_cluster_tests {
 
             proramming
             programming techniques",
+            &mut reporter,
         );
         assert_eq!(5, clusters.num_tag());
     }
 
     #[test]
-    #[should_panic]
     fn test_tag_duplication() {
+        let mut reporter = Reporter::new();
         let mut clusters = BlogClusters::new();
         clusters.add_tags(
+            Path::new("tags.txt"),
             "
             life
             things about current life
             life
             things about current life
         ",
+            &mut reporter,
         );
+        assert_eq!(1, clusters.num_tag());
+        match reporter.errors() {
+            [Error::DuplicateTag(location, tag)] => {
+                assert_eq!(&Location::new("tags.txt", 4, 1), location);
+                assert_eq!("life", tag);
+            }
+            x => panic!("{:?}", x),
+        }
     }
 
     #[test]
     fn test_blog_adding() {
+        let mut reporter = Reporter::new();
         let mut clusters = BlogClusters::new();
         clusters.add_tags(
+            Path::new("tags.txt"),
             "life
             things about current life
 
@@ -268,11 +355,13 @@ mod blog_cluster_tests {
 
             fun
             maybe some gameplay",
+            &mut reporter,
         );
 
-        clusters.add_blogs(&vec![(
-            "test-blog".to_string(),
-            "Test Blog
+        clusters.add_blogs(
+            &[(
+                PathBuf::from("test-blog.md"),
+                "Test Blog
                 2000/9/27
                 life | work | fun
                 ---
@@ -280,8 +369,11 @@ mod blog_cluster_tests {
                 ---
                 ololololololo
                 "
-            .to_string(),
-        )]);
+                .to_string(),
+            )],
+            &mut reporter,
+        );
+        assert!(!reporter.has_errors());
         let blogs = clusters.get_blogs();
         assert_eq!(blogs.len(), 1);
         let blog = &blogs[0];
@@ -293,4 +385,193 @@ mod blog_cluster_tests {
         assert_eq!(blog.preview, "lolololololol");
         assert_eq!(blog.content, "ololololololo");
     }
+
+    #[test]
+    fn test_blog_adding_separator_in_content() {
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_tags(
+            Path::new("tags.txt"),
+            "life
+            things about current life",
+            &mut reporter,
+        );
+
+        clusters.add_blogs(
+            &[(
+                PathBuf::from("test-blog.md"),
+                "Test Blog
+2000/9/27
+life
+---
+lolololololol
+---
+```yaml
+---
+a: b
+```
+
+---
+ololololololo
+"
+                .to_string(),
+            )],
+            &mut reporter,
+        );
+        assert!(!reporter.has_errors());
+        let blog = &clusters.get_blogs()[0];
+        assert_eq!(blog.preview, "lolololololol");
+        assert_eq!(
+            blog.content,
+            "```yaml\n---\na: b\n```\n\n---\nololololololo"
+        );
+    }
+
+    #[test]
+    fn test_blog_adding_errors() {
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_tags(
+            Path::new("tags.txt"),
+            "life
+            things about current life",
+            &mut reporter,
+        );
+        clusters.add_blogs(
+            &[
+                (
+                    PathBuf::from("blogs/bad-date.md"),
+                    "Bad Date\n2001/2/29\nlife\n---\npreview\n---\ncontent".to_string(),
+                ),
+                (
+                    PathBuf::from("blogs/bad-tag.md"),
+                    "Bad Tag\n2001/2/28\nlife | work\n---\npreview\n---\ncontent".to_string(),
+                ),
+                (
+                    PathBuf::from("blogs/bad-name.md"),
+                    "Bad Title\n2001/2/28\nlife\n---\npreview\n---\ncontent".to_string(),
+                ),
+                (
+                    PathBuf::from("blogs/good.md"),
+                    "Good\n2001/2/28\nlife\n---\npreview\n---\ncontent".to_string(),
+                ),
+                (
+                    PathBuf::from("blogs/old/good.md"),
+                    "Good\n2000/2/28\nlife\n---\npreview\n---\ncontent".to_string(),
+                ),
+                (
+                    PathBuf::from("blogs/renamed.md"),
+                    "+++\ntitle = \"Renamed\"\ndate = 2001-02-28\ntags = [\"life\"]\nslug = \"Good\"\n+++\npreview\n---\ncontent".to_string(),
+                ),
+            ],
+            &mut reporter,
+        );
+        // Valid blogs are still added
+        assert_eq!(1, clusters.num_blog());
+        let errors: Vec<String> = reporter.errors().iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "blogs/bad-date.md:2:1: invalid date: 2001/2/29",
+                "blogs/bad-tag.md:3:8: unknown tag: work",
+                "blogs/bad-name.md:1:1: title \"Bad Title\" doesn't correspond to file name \"bad-name\"",
+                "blogs/old/good.md:1:1: duplicate slug \"good\", already used by blogs/good.md",
+                "blogs/renamed.md:5:9: duplicate slug \"good\", already used by blogs/good.md",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blog_removing() {
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_tags(
+            Path::new("tags.txt"),
+            "life
+            things about current life",
+            &mut reporter,
+        );
+        let blog = |title: &str, date: &str| {
+            (
+                PathBuf::from(format!("{}.md", title)),
+                format!("{}\n{}\nlife\n---\npreview\n---\ncontent", title, date),
+            )
+        };
+        clusters.add_blogs(
+            &[blog("a", "2000/9/27"), blog("b", "2001/9/27")],
+            &mut reporter,
+        );
+        assert_eq!(Some(&vec![0, 1]), clusters.get_tag_blogs(0));
+
+        // Changed blog is added again, with its slug free
+        clusters.remove_blogs(&[PathBuf::from("b.md")]);
+        assert_eq!(1, clusters.num_blog());
+        assert_eq!(Some(&vec![0]), clusters.get_tag_blogs(0));
+        clusters.add_blogs(&[blog("b", "2000/1/1")], &mut reporter);
+        assert!(!reporter.has_errors());
+        let titles: Vec<&str> = clusters
+            .get_blogs()
+            .iter()
+            .map(|x| x.title.as_str())
+            .collect();
+        assert_eq!(vec!["a", "b"], titles);
+        assert_eq!(Some(&vec![0, 1]), clusters.get_tag_blogs(0));
+    }
+
+    #[test]
+    fn test_blog_adding_front_matter() {
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_tags(
+            Path::new("tags.txt"),
+            "life
+            things about current life
+
+            work
+            about my works",
+            &mut reporter,
+        );
+
+        clusters.add_blogs(
+            &[
+                (
+                    PathBuf::from("test-blog.md"),
+                    "+++
+title = \"Test Blog\"
+date = 2000-09-27
+tags = [\"life\", \"work\"]
+slug = \"Hello World\"
+summary = \"lolololololol\"
++++
+ololololololo
+"
+                    .to_string(),
+                ),
+                (
+                    PathBuf::from("draft-blog.md"),
+                    "---
+title: Draft Blog
+date: 2000/9/28
+draft: true
+---
+lolololololol
+---
+ololololololo
+"
+                    .to_string(),
+                ),
+            ],
+            &mut reporter,
+        );
+        assert!(!reporter.has_errors());
+        let blogs = clusters.get_blogs();
+        assert_eq!(blogs.len(), 1);
+        let blog = &blogs[0];
+        assert_eq!(blog.day, 27);
+        assert_eq!(blog.tags.len(), 2);
+        assert_eq!(blog.title, "Test Blog");
+        assert_eq!(blog.path(), "hello-world.html");
+        assert_eq!(blog.preview, "lolololololol");
+        assert_eq!(blog.content, "ololololololo");
+    }
 }
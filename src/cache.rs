@@ -0,0 +1,294 @@
+//! Build cache.
+//!
+//! Kept between builds in the cache directory (see `paths.cache`):
+//! ```txt
+//! .blogen-cache/
+//!     pages         hash of the inputs of each page written, with its path
+//!     <hash>.html   rendered content of a blog, by hash of its markdown and
+//!                   highlighting settings
+//!     <hash>.code   highlighted code block, by hash of its language, code and
+//!                   highlighting settings
+//! ```
+//! Hashes start with the generator version, so a new blogen starts over.
+//! Broken or missing cache files are just misses. A page whose inputs hash is
+//! the same as when it was written isn't generated again. Rendered contents
+//! not used by a build are removed, code blocks are kept since blogs served
+//! from the cache don't touch theirs.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const PAGES_FILE: &str = "pages";
+
+// FNV-1a, `DefaultHasher` isn't stable between Rust releases
+pub struct Hasher(u64);
+
+impl Hasher {
+    pub fn new() -> Self {
+        let mut hasher = Hasher(0xcbf2_9ce4_8422_2325);
+        hasher.write(VERSION.as_bytes());
+        hasher
+    }
+
+    // Length goes first, so "ab" + "c" differs from "a" + "bc"
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Highlighted code blocks, shared by threads rendering blogs
+#[derive(Default)]
+pub struct Highlights {
+    dir: Option<PathBuf>,
+    added: Mutex<Vec<(u64, String)>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl Highlights {
+    fn entry(dir: &Path, key: u64) -> PathBuf {
+        dir.join(format!("{:016x}.code", key))
+    }
+
+    // Highlighted code by key, `highlight` is only called on a miss. Code it
+    // can't highlight isn't cached.
+    pub fn get_or(
+        &self,
+        key: u64,
+        highlight: impl FnOnce() -> Result<Option<String>, String>,
+    ) -> Result<Option<String>, String> {
+        let cached = self
+            .dir
+            .as_ref()
+            .and_then(|dir| fs::read_to_string(Self::entry(dir, key)).ok());
+        if let Some(x) = cached {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(x));
+        }
+        let result = highlight()?;
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        if let Some(x) = &result {
+            self.added.lock().unwrap().push((key, x.clone()));
+        }
+        Ok(result)
+    }
+
+    // Hits and misses so far
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Cache {
+    // In memory only if None
+    dir: Option<PathBuf>,
+    // Inputs hash of pages written by the last build
+    pages: BTreeMap<String, u64>,
+    // Rendered contents read or added in this build, and all used by it
+    rendered: HashMap<u64, String>,
+    added: Vec<u64>,
+    used: HashSet<u64>,
+    pub highlights: Highlights,
+}
+
+impl Cache {
+    pub fn load(dir: &Path) -> Self {
+        let pages = fs::read_to_string(dir.join(PAGES_FILE))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|x| {
+                let (key, page) = x.split_once(' ')?;
+                Some((page.to_string(), u64::from_str_radix(key, 16).ok()?))
+            })
+            .collect();
+        Cache {
+            dir: Some(dir.to_path_buf()),
+            pages,
+            highlights: Highlights {
+                dir: Some(dir.to_path_buf()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn entry(dir: &Path, key: u64) -> PathBuf {
+        dir.join(format!("{:016x}.html", key))
+    }
+
+    // Inputs of the page are the same as when it was written, and it's still
+    // there
+    pub fn unchanged(&self, page: &str, key: u64, output_dir: &Path) -> bool {
+        self.pages.get(page) == Some(&key) && output_dir.join(page).is_file()
+    }
+
+    // Pages written by the last build
+    pub fn pages(&self) -> impl Iterator<Item = &String> {
+        self.pages.keys()
+    }
+
+    // Rendered content by key if it's cached
+    pub fn get(&mut self, key: u64) -> Option<String> {
+        if let Some(x) = self.rendered.get(&key) {
+            return Some(x.clone());
+        }
+        let cached = fs::read_to_string(Self::entry(self.dir.as_ref()?, key)).ok()?;
+        self.rendered.insert(key, cached.clone());
+        self.used.insert(key);
+        Some(cached)
+    }
+
+    // If rendered content is cached, without reading it
+    pub fn contains(&mut self, key: u64) -> bool {
+        let cached = self.rendered.contains_key(&key)
+            || self
+                .dir
+                .as_ref()
+                .is_some_and(|dir| Self::entry(dir, key).is_file());
+        if cached {
+            self.used.insert(key);
+        }
+        cached
+    }
+
+    pub fn insert(&mut self, key: u64, rendered: String) {
+        self.rendered.insert(key, rendered);
+        self.added.push(key);
+        self.used.insert(key);
+    }
+
+    // Write rendered contents added. Given the inputs hash of every page of a
+    // build, they are remembered and contents not used are removed.
+    pub fn save(&self, pages: Option<&BTreeMap<String, u64>>) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)?;
+        for key in self.added.iter() {
+            fs::write(Self::entry(dir, *key), &self.rendered[key])?;
+        }
+        for (key, highlighted) in self.highlights.added.lock().unwrap().iter() {
+            fs::write(Highlights::entry(dir, *key), highlighted)?;
+        }
+        let Some(pages) = pages else {
+            return Ok(());
+        };
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let used = path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .and_then(|x| u64::from_str_radix(x, 16).ok())
+                .is_some_and(|x| self.used.contains(&x));
+            if path.extension().is_some_and(|x| x == "html") && !used {
+                fs::remove_file(path)?;
+            }
+        }
+        let mut raw = String::new();
+        for (page, key) in pages {
+            raw.push_str(&format!("{:016x} {}\n", key, page));
+        }
+        fs::write(dir.join(PAGES_FILE), raw)
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        let mut a = Hasher::new();
+        a.write(b"ab");
+        a.write(b"c");
+        let mut b = Hasher::new();
+        b.write(b"a");
+        b.write(b"bc");
+        assert_ne!(a.finish(), b.finish());
+        let mut c = Hasher::new();
+        c.write(b"ab");
+        c.write(b"c");
+        assert_eq!(a.finish(), c.finish());
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join(format!("blogen-cache-{}", std::process::id()));
+        let output_dir = dir.join("output");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(output_dir.join("index.html"), "").unwrap();
+        let cache_dir = dir.join(".blogen-cache");
+        let pages = |x: &[(&str, u64)]| -> BTreeMap<String, u64> {
+            x.iter()
+                .map(|(page, key)| (page.to_string(), *key))
+                .collect()
+        };
+
+        let mut cache = Cache::load(&cache_dir);
+        assert!(!cache.unchanged("index.html", 1, &output_dir));
+        assert_eq!(cache.get(10), None);
+        assert!(!cache.contains(10));
+        cache.insert(10, "<h1>a</h1>".to_string());
+        assert_eq!(cache.get(10).unwrap(), "<h1>a</h1>");
+        cache.save(Some(&pages(&[("index.html", 1)]))).unwrap();
+
+        let mut cache = Cache::load(&cache_dir);
+        assert!(cache.unchanged("index.html", 1, &output_dir));
+        assert!(!cache.unchanged("index.html", 2, &output_dir));
+        assert!(!cache.unchanged("cluster.html", 1, &output_dir));
+        assert!(cache.contains(10));
+        cache.insert(12, "<h1>c</h1>".to_string());
+        // A failed build keeps the pages written before
+        cache.save(None).unwrap();
+        assert!(Cache::load(&cache_dir).unchanged("index.html", 1, &output_dir));
+
+        // Unused contents are removed
+        let mut cache = Cache::load(&cache_dir);
+        assert!(cache.get(12).is_some());
+        cache.save(Some(&pages(&[]))).unwrap();
+        assert!(!Cache::entry(&cache_dir, 10).exists());
+        assert!(Cache::entry(&cache_dir, 12).exists());
+
+        // Code blocks are kept
+        let cache = Cache::load(&cache_dir);
+        let highlighted = cache
+            .highlights
+            .get_or(7, || Ok(Some("<span>a</span>".to_string())));
+        assert_eq!(highlighted.unwrap().unwrap(), "<span>a</span>");
+        assert!(cache
+            .highlights
+            .get_or(8, || Err("broken".to_string()))
+            .is_err());
+        // Not highlighted, not cached
+        assert_eq!(cache.highlights.get_or(9, || Ok(None)).unwrap(), None);
+        cache.save(Some(&pages(&[]))).unwrap();
+        let cache = Cache::load(&cache_dir);
+        let highlighted = cache.highlights.get_or(7, || panic!("highlighted again"));
+        assert_eq!(highlighted.unwrap().unwrap(), "<span>a</span>");
+        assert_eq!(cache.highlights.get_or(9, || Ok(None)).unwrap(), None);
+        assert_eq!(cache.highlights.stats(), (1, 1));
+
+        // Pages removed from the output aren't skipped
+        fs::remove_file(output_dir.join("index.html")).unwrap();
+        cache.save(Some(&pages(&[("index.html", 1)]))).unwrap();
+        assert!(!Cache::load(&cache_dir).unchanged("index.html", 1, &output_dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,383 @@
+//! Command line.
+//!
+//! ```txt
+//! blogen [build|new|check|tags|serve|watch|fmt|dump|highlight-css] [args] [flags]
+//! ```
+//! Without a subcommand it builds. Flags of the config (see `config`) work for
+//! every subcommand. Exit codes are `SUCCESS`, `FAILURE` when problems are
+//! found in blogs or templates, and `USAGE` for wrong arguments.
+
+use crate::config::{parse_flags, Flags};
+
+pub const SUCCESS: i32 = 0;
+pub const FAILURE: i32 = 1;
+pub const USAGE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subcommand {
+    Build,
+    New,
+    Check,
+    Tags,
+    Serve,
+    Watch,
+    Fmt,
+    Dump,
+    HighlightCss,
+}
+
+// Name, positional arguments, summary and details of each subcommand
+const SUBCOMMANDS: [(Subcommand, &str, &str, &str, &str); 9] = [
+    (
+        Subcommand::Build,
+        "build",
+        "",
+        "Generate the site into the output directory.",
+        "Nothing is written if any problem is found.",
+    ),
+    (
+        Subcommand::New,
+        "new",
+        "\"<title>\"",
+        "Create a draft blog dated today.",
+        "The file is named after the title in the blog directory, set `draft = false` in\n\
+         it to publish.",
+    ),
+    (
+        Subcommand::Check,
+        "check",
+        "",
+        "Check blogs, tags and templates without writing anything.",
+        "",
+    ),
+    (
+        Subcommand::Tags,
+        "tags",
+        "",
+        "List tags with the number of blogs carrying them.",
+        "",
+    ),
+    (
+        Subcommand::Serve,
+        "serve",
+        "",
+        "Build, then serve the output directory with live reload.",
+        "Rebuilds like `watch`, open pages are reloaded after rebuilding.\n\n\
+         Flags:\n    --port <port>    Port to listen on, 8000 by default",
+    ),
+    (
+        Subcommand::Watch,
+        "watch",
+        "",
+        "Build, then rebuild when blogs, tags or templates change.",
+        "Only pages whose inputs changed are generated again, e.g. editing a blog\n\
+         rebuilds its page and the listing pages. Problems are printed and watching goes on.",
+    ),
+    (
+        Subcommand::Fmt,
+        "fmt",
+        "<template>...",
+        "Format templates in place.",
+        "Markers are written as `<!--symbol--> name <!--symbol-->`, everything else is\n\
+         kept. Partials are formatted as content.",
+    ),
+    (
+        Subcommand::Dump,
+        "dump",
+        "<template>...",
+        "Print symbols of templates as a grammar.",
+        "",
+    ),
+    (
+        Subcommand::HighlightCss,
+        "highlight-css",
+        "",
+        "Print the stylesheet of a code highlighting theme.",
+        "It's for `highlight.style = \"classes\"`, save it as a CSS file linked by the\n\
+         templates. Changing the theme is then swapping the file. With a dark theme,\n\
+         it's used when the reader prefers a dark color scheme.\n\n\
+         Flags:\n    --theme <name>         Theme, `highlight.theme` by default\n    \
+         --dark-theme <name>    Dark theme, `highlight.dark_theme` by default",
+    ),
+];
+
+const CONFIG_FLAGS: &str = "Config flags, overriding `blogen.toml` and environment variables:
+    --config <file>
+    --title <title>, --base-url <url>, --author <author>, --language <language>
+    --blogs <dir>, --tags <file>, --output <dir>, --cache <dir>
+    --homepage-template <file>, --blog-template <file>,
+    --cluster-template <file>, --tag-template <file>
+    --jobs <n>    Threads rendering blogs, 0 (the default) means one per core
+    --highlight-style <inline|classes>, --highlight-theme <name>,
+    --highlight-dark-theme <name>";
+
+const EXIT_CODES: &str = "Exit codes:
+    0    Ok
+    1    Problems found in blogs, tags or templates
+    2    Wrong arguments";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    // Help of the subcommand, or of all if None
+    Help(Option<Subcommand>),
+    Run {
+        subcommand: Subcommand,
+        // Positional arguments
+        args: Vec<String>,
+        port: u16,
+        flags: Flags,
+    },
+}
+
+pub fn usage(subcommand: Option<Subcommand>) -> String {
+    match subcommand {
+        None => {
+            let mut result = String::from(
+                "Generate the blog.\n\nUsage: blogen [subcommand] [args] [flags]\n\nSubcommands:\n",
+            );
+            let width = SUBCOMMANDS.iter().map(|x| x.1.len()).max().unwrap_or(0);
+            for (_, name, _, summary, _) in SUBCOMMANDS.iter() {
+                result.push_str(&format!("    {:<width$} {}\n", name, summary));
+            }
+            result.push_str(&format!(
+                "\nRun `blogen <subcommand> --help` for more.\n\n{}\n\n{}\n",
+                CONFIG_FLAGS, EXIT_CODES
+            ));
+            result
+        }
+        Some(subcommand) => {
+            let (_, name, args, summary, details) =
+                SUBCOMMANDS.iter().find(|x| x.0 == subcommand).unwrap();
+            let args = if args.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", args)
+            };
+            let mut result = format!("{}\n\nUsage: blogen {}{} [flags]\n", summary, name, args);
+            if !details.is_empty() {
+                result.push_str(&format!("\n{}\n", details));
+            }
+            result.push_str(&format!("\n{}\n\n{}\n", CONFIG_FLAGS, EXIT_CODES));
+            result
+        }
+    }
+}
+
+// Parse arguments after the program name
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let mut args = args.iter().peekable();
+    // Builds if not given
+    let mut given = true;
+    let subcommand = match args.peek() {
+        Some(x) if !x.starts_with('-') => {
+            let found = SUBCOMMANDS.iter().find(|(_, name, ..)| name == x);
+            match found {
+                Some(x) => {
+                    args.next();
+                    x.0
+                }
+                None if x.as_str() == "help" => {
+                    args.next();
+                    let topic = args.next();
+                    return match topic {
+                        None => Ok(Command::Help(None)),
+                        Some(topic) => match SUBCOMMANDS.iter().find(|x| x.1 == topic) {
+                            Some(x) => Ok(Command::Help(Some(x.0))),
+                            None => Err(format!("unknown subcommand \"{}\".", topic)),
+                        },
+                    };
+                }
+                None => return Err(format!("unknown subcommand \"{}\".", x)),
+            }
+        }
+        _ => {
+            given = false;
+            Subcommand::Build
+        }
+    };
+
+    let mut positional = Vec::new();
+    let mut port = None;
+    // Left for the config
+    let mut config_flags = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help(given.then_some(subcommand)));
+        }
+        if !arg.starts_with("--") {
+            positional.push(arg.clone());
+            continue;
+        }
+        // All flags take a value
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), args.next().cloned()),
+        };
+        let value = value.ok_or_else(|| format!("\"{}\" needs a value.", flag))?;
+        let theme_flag = match flag {
+            "--theme" => Some("--highlight-theme"),
+            "--dark-theme" => Some("--highlight-dark-theme"),
+            _ => None,
+        };
+        if let (Some(theme_flag), Subcommand::HighlightCss) = (theme_flag, subcommand) {
+            config_flags.push(theme_flag.to_string());
+            config_flags.push(value);
+        } else if flag == "--port" && subcommand == Subcommand::Serve {
+            port = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid port \"{}\".", value))?,
+            );
+        } else {
+            config_flags.push(flag.to_string());
+            config_flags.push(value);
+        }
+    }
+
+    let (_, name, expected, ..) = SUBCOMMANDS.iter().find(|x| x.0 == subcommand).unwrap();
+    let arity_ok = match subcommand {
+        Subcommand::New => positional.len() == 1,
+        Subcommand::Fmt | Subcommand::Dump => !positional.is_empty(),
+        _ => positional.is_empty(),
+    };
+    if !arity_ok {
+        return Err(if expected.is_empty() {
+            format!("\"{}\" takes no arguments.", name)
+        } else {
+            format!("usage: blogen {} {}", name, expected)
+        });
+    }
+    Ok(Command::Run {
+        subcommand,
+        args: positional,
+        port: port.unwrap_or(8000),
+        flags: parse_flags(&config_flags)?,
+    })
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn parse_str(args: &str) -> Result<Command, String> {
+        let args: Vec<String> = args.split_whitespace().map(|x| x.to_string()).collect();
+        parse(&args)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_str("").unwrap(),
+            Command::Run {
+                subcommand: Subcommand::Build,
+                args: Vec::new(),
+                port: 8000,
+                flags: Flags::default(),
+            }
+        );
+        assert_eq!(
+            parse_str("--output public").unwrap(),
+            parse_str("build --output=public").unwrap()
+        );
+        assert_eq!(
+            parse_str("serve --port 4000 --config x.toml").unwrap(),
+            Command::Run {
+                subcommand: Subcommand::Serve,
+                args: Vec::new(),
+                port: 4000,
+                flags: Flags {
+                    config_path: Some(PathBuf::from("x.toml")),
+                    settings: Vec::new(),
+                },
+            }
+        );
+        assert_eq!(
+            parse_str("new Hello --blogs drafts").unwrap(),
+            Command::Run {
+                subcommand: Subcommand::New,
+                args: vec!["Hello".to_string()],
+                port: 8000,
+                flags: Flags {
+                    config_path: None,
+                    settings: vec![("paths.blogs", "drafts".to_string())],
+                },
+            }
+        );
+        assert_eq!(
+            parse_str("highlight-css --theme Solarized --dark-theme=x").unwrap(),
+            Command::Run {
+                subcommand: Subcommand::HighlightCss,
+                args: Vec::new(),
+                port: 8000,
+                flags: Flags {
+                    config_path: None,
+                    settings: vec![
+                        ("highlight.theme", "Solarized".to_string()),
+                        ("highlight.dark_theme", "x".to_string())
+                    ],
+                },
+            }
+        );
+        assert_eq!(parse_str("--help").unwrap(), Command::Help(None));
+        assert_eq!(
+            parse_str("build --help").unwrap(),
+            Command::Help(Some(Subcommand::Build))
+        );
+        assert_eq!(
+            parse_str("tags -h").unwrap(),
+            Command::Help(Some(Subcommand::Tags))
+        );
+        assert_eq!(parse_str("help").unwrap(), Command::Help(None));
+        assert_eq!(
+            parse_str("help new").unwrap(),
+            Command::Help(Some(Subcommand::New))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_str("biuld").unwrap_err(),
+            "unknown subcommand \"biuld\"."
+        );
+        assert_eq!(
+            parse_str("new").unwrap_err(),
+            "usage: blogen new \"<title>\""
+        );
+        assert_eq!(
+            parse_str("check x").unwrap_err(),
+            "\"check\" takes no arguments."
+        );
+        assert_eq!(
+            parse_str("fmt").unwrap_err(),
+            "usage: blogen fmt <template>..."
+        );
+        assert_eq!(
+            parse_str("build --port 1").unwrap_err(),
+            "unknown flag \"--port\"."
+        );
+        assert_eq!(
+            parse_str("serve --port x").unwrap_err(),
+            "invalid port \"x\"."
+        );
+        assert_eq!(
+            parse_str("build --output").unwrap_err(),
+            "\"--output\" needs a value."
+        );
+    }
+
+    #[test]
+    fn test_usage() {
+        assert!(usage(None).contains(
+            "    serve         Build, then serve the output directory with live reload.\n"
+        ));
+        assert!(usage(Some(Subcommand::New)).starts_with(
+            "Create a draft blog dated today.\n\nUsage: blogen new \"<title>\" [flags]\n"
+        ));
+        assert!(usage(Some(Subcommand::Check)).contains("Usage: blogen check [flags]\n"));
+        assert!(usage(None).contains(
+            "    watch         Build, then rebuild when blogs, tags or templates change.\n"
+        ));
+    }
+}
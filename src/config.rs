@@ -0,0 +1,535 @@
+//! Site configuration.
+//!
+//! Read from `blogen.toml` in the working directory, or the file given by
+//! `--config`. Every setting has a default, so the file is optional:
+//! ```toml
+//! [site]
+//! title = "ldm0's Blog"
+//! base_url = "https://example.com/"
+//! author = "ldm0"
+//! language = "en"
+//!
+//! [paths]
+//! blogs = "blogs"
+//! tags = "assets/tags.txt"
+//! output = "output"
+//! cache = ".blogen-cache"
+//!
+//! [templates]
+//! homepage = "assets/template_homepage.html"
+//! blog = "assets/template_blog.html"
+//! cluster = "assets/template_cluster.html"
+//! tag = "assets/template_tag.html"
+//!
+//! [build]
+//! jobs = 0
+//!
+//! [highlight]
+//! style = "inline"
+//! theme = "base16-ocean.light"
+//! dark_theme = ""
+//!
+//! # Languages of code blocks to extensions of syntaxes, over the builtin ones
+//! [languages]
+//! mermaid = "txt"
+//! ```
+//! Paths in the file are relative to the file. Environment variables (`.env`
+//! works too) override the file, flags override both, see `SETTINGS`.
+//! Languages are only set in the file.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Location};
+
+pub const CONFIG_PATH: &str = "blogen.toml";
+
+// Settings besides the file, with their environment variable and flag
+const SETTINGS: [(&str, &str, &str); 16] = [
+    ("site.title", "SITE_TITLE", "--title"),
+    ("site.base_url", "SITE_BASE_URL", "--base-url"),
+    ("site.author", "SITE_AUTHOR", "--author"),
+    ("site.language", "SITE_LANGUAGE", "--language"),
+    ("paths.blogs", "BLOG_PATH", "--blogs"),
+    ("paths.tags", "TAGS_PATH", "--tags"),
+    ("paths.output", "OUTPUT_PATH", "--output"),
+    ("paths.cache", "CACHE_PATH", "--cache"),
+    (
+        "templates.homepage",
+        "TEMPLATE_HOMEPAGE_PATH",
+        "--homepage-template",
+    ),
+    ("templates.blog", "TEMPLATE_BLOG_PATH", "--blog-template"),
+    (
+        "templates.cluster",
+        "TEMPLATE_CLUSTER_PATH",
+        "--cluster-template",
+    ),
+    ("templates.tag", "TEMPLATE_TAG_PATH", "--tag-template"),
+    ("build.jobs", "BLOGEN_JOBS", "--jobs"),
+    ("highlight.style", "HIGHLIGHT_STYLE", "--highlight-style"),
+    ("highlight.theme", "HIGHLIGHT_THEME", "--highlight-theme"),
+    (
+        "highlight.dark_theme",
+        "HIGHLIGHT_DARK_THEME",
+        "--highlight-dark-theme",
+    ),
+];
+
+// Settings written as numbers in the file, others are strings
+const NUMBERS: [&str; 1] = ["build.jobs"];
+
+// Filled in `site.*` slots
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Site {
+    pub title: String,
+    pub base_url: String,
+    pub author: String,
+    pub language: String,
+}
+
+// How code blocks are highlighted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Highlight {
+    // Spans with class names instead of inline styles, their stylesheet is
+    // printed by `blogen highlight-css`
+    pub classes: bool,
+    // Theme of inline styles, or of classes by default
+    pub theme: String,
+    // Theme of classes if `prefers-color-scheme` is dark, empty in the file
+    // for none
+    pub dark_theme: Option<String>,
+    // Language of code blocks to syntax extension, see `lang2ext`
+    pub languages: BTreeMap<String, String>,
+}
+
+impl Default for Highlight {
+    fn default() -> Self {
+        Highlight {
+            classes: false,
+            theme: "base16-ocean.light".to_string(),
+            dark_theme: None,
+            languages: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub site: Site,
+    pub blog_dir: PathBuf,
+    pub tags_path: PathBuf,
+    pub output_dir: PathBuf,
+    // See `cache`
+    pub cache_dir: PathBuf,
+    pub homepage_template: PathBuf,
+    pub blog_template: PathBuf,
+    pub cluster_template: PathBuf,
+    pub tag_template: PathBuf,
+    // Threads rendering blogs, 0 means one per core
+    pub jobs: usize,
+    pub highlight: Highlight,
+    // Where the config file sets each key, for values checked after loading
+    pub locations: BTreeMap<String, Location>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            site: Site::default(),
+            blog_dir: PathBuf::from("blogs"),
+            tags_path: PathBuf::from("assets/tags.txt"),
+            output_dir: PathBuf::from("output"),
+            cache_dir: PathBuf::from(".blogen-cache"),
+            homepage_template: PathBuf::from("assets/template_homepage.html"),
+            blog_template: PathBuf::from("assets/template_blog.html"),
+            cluster_template: PathBuf::from("assets/template_cluster.html"),
+            tag_template: PathBuf::from("assets/template_tag.html"),
+            jobs: 0,
+            highlight: Highlight::default(),
+            locations: BTreeMap::new(),
+        }
+    }
+}
+
+// Given on the command line
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    // `--config`
+    pub config_path: Option<PathBuf>,
+    // Settings by key, e.g. `--output public` or `--output=public`
+    pub settings: Vec<(&'static str, String)>,
+}
+
+pub fn parse_flags(args: &[String]) -> Result<Flags, String> {
+    let mut flags = Flags::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let key = match flag {
+            "--config" => None,
+            _ => match SETTINGS.iter().find(|x| x.2 == flag) {
+                Some(x) => Some(x.0),
+                None => return Err(format!("unknown flag \"{}\".", flag)),
+            },
+        };
+        let value = match value.or_else(|| args.next().cloned()) {
+            Some(x) => x,
+            None => return Err(format!("\"{}\" needs a value.", flag)),
+        };
+        match key {
+            Some(key) => flags.settings.push((key, value)),
+            None => flags.config_path = Some(PathBuf::from(value)),
+        }
+    }
+    Ok(flags)
+}
+
+// Parts of the dotted key at the start of `line`, unquoted, and the rest of
+// the line from the first `=` or `]` outside quotes
+fn key_parts(line: &str) -> (Vec<String>, &str) {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some('"'), '\\') => part.extend(chars.next().map(|x| x.1)),
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => part.push(c),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '.') => parts.push(std::mem::take(&mut part)),
+            (None, '=' | ']') => {
+                parts.push(part);
+                return (parts, &line[i..]);
+            }
+            (None, _) if c.is_whitespace() => {}
+            (None, _) => part.push(c),
+        }
+    }
+    parts.push(part);
+    (parts, "")
+}
+
+// Where `key` is set in the file, for diagnostics. The key is looked up in its
+// own table, an inline table holding it is located by its own key.
+fn key_location(path: &Path, raw: &str, key: &[&str]) -> Location {
+    let mut table = Vec::new();
+    for (i, line) in raw.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(header) = trimmed.strip_prefix('[') {
+            let (parts, rest) = key_parts(header.trim_start_matches('['));
+            if rest.starts_with(']') {
+                table = parts;
+            }
+            continue;
+        }
+        let (parts, rest) = key_parts(trimmed);
+        if !rest.starts_with('=') {
+            continue;
+        }
+        let full: Vec<&str> = table.iter().chain(&parts).map(|x| x.as_str()).collect();
+        if key.starts_with(&full) {
+            let column = line.chars().count() - trimmed.chars().count() + 1;
+            return Location::new(path, i + 1, column);
+        }
+    }
+    Location::file(path)
+}
+
+impl Config {
+    // Set by key like `paths.output`, relative paths are joined to `base`.
+    fn set(&mut self, key: &str, value: &str, base: &Path) -> Result<(), String> {
+        let path = || base.join(value);
+        match key {
+            "site.title" => self.site.title = value.to_string(),
+            "site.base_url" => self.site.base_url = value.to_string(),
+            "site.author" => self.site.author = value.to_string(),
+            "site.language" => self.site.language = value.to_string(),
+            "paths.blogs" => self.blog_dir = path(),
+            "paths.tags" => self.tags_path = path(),
+            "paths.output" => self.output_dir = path(),
+            "paths.cache" => self.cache_dir = path(),
+            "templates.homepage" => self.homepage_template = path(),
+            "templates.blog" => self.blog_template = path(),
+            "templates.cluster" => self.cluster_template = path(),
+            "templates.tag" => self.tag_template = path(),
+            "build.jobs" => {
+                self.jobs = value
+                    .parse()
+                    .map_err(|_| format!("\"{}\" should be a number, not \"{}\".", key, value))?
+            }
+            "highlight.style" => {
+                self.highlight.classes = match value {
+                    "inline" => false,
+                    "classes" => true,
+                    _ => {
+                        return Err(format!(
+                            "\"{}\" should be \"inline\" or \"classes\", not \"{}\".",
+                            key, value
+                        ))
+                    }
+                }
+            }
+            "highlight.theme" => self.highlight.theme = value.to_string(),
+            "highlight.dark_theme" => {
+                self.highlight.dark_theme = Some(value.to_string()).filter(|x| !x.is_empty())
+            }
+            _ => match key.strip_prefix("languages.") {
+                Some(lang) => {
+                    self.highlight
+                        .languages
+                        .insert(lang.to_string(), value.to_string());
+                }
+                None => return Err(format!("unknown key \"{}\".", key)),
+            },
+        }
+        Ok(())
+    }
+
+    // Parse the config file over the defaults
+    pub fn parse(path: &Path, raw: &str) -> Result<Self, Error> {
+        let table: toml::Table = raw.parse().map_err(|err: toml::de::Error| {
+            let offset = err.span().map_or(0, |x| x.start);
+            let before = &raw[..offset.min(raw.len())];
+            let location = Location::new(
+                path,
+                before.matches('\n').count() + 1,
+                before.rsplit('\n').next().unwrap_or("").chars().count() + 1,
+            );
+            Error::Config(Some(location), err.message().to_string())
+        })?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut config = Config::default();
+        for (section, value) in table {
+            let toml::Value::Table(value) = value else {
+                return Err(Error::Config(
+                    Some(key_location(path, raw, &[&section])),
+                    format!("\"{}\" should be a table.", section),
+                ));
+            };
+            for (key, value) in value {
+                let location = key_location(path, raw, &[&section, &key]);
+                let invalid = |reason: String| Error::Config(Some(location.clone()), reason);
+                let full_key = format!("{}.{}", section, key);
+                let number = NUMBERS.contains(&full_key.as_str());
+                let value = match value {
+                    toml::Value::String(x) if !number => x,
+                    toml::Value::Integer(x) if number => x.to_string(),
+                    _ if number => {
+                        return Err(invalid(format!("\"{}\" should be a number.", full_key)))
+                    }
+                    _ => return Err(invalid(format!("\"{}\" should be a string.", full_key))),
+                };
+                config.set(&full_key, &value, base).map_err(invalid)?;
+                config.locations.insert(full_key, location);
+            }
+        }
+        Ok(config)
+    }
+
+    // The config file, then environment variables, then flags. A missing
+    // config file is fine unless it's given explicitly.
+    pub fn load(flags: &Flags, var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let config_path = flags.config_path.as_deref();
+        let path = config_path.unwrap_or(Path::new(CONFIG_PATH));
+        let mut config = match fs::read_to_string(path) {
+            Ok(raw) => Self::parse(path, &raw)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && config_path.is_none() => {
+                Config::default()
+            }
+            Err(err) => return Err(Error::Io(Location::file(path), err)),
+        };
+        // Overridden values aren't in the file, invalid ones are told by the
+        // variable or flag
+        for (key, name, _) in SETTINGS {
+            if let Some(value) = var(name) {
+                config
+                    .set(key, &value, Path::new(""))
+                    .map_err(|reason| Error::Config(None, format!("{}: {}", name, reason)))?;
+                config.locations.remove(key);
+            }
+        }
+        for (key, value) in flags.settings.iter() {
+            let flag = SETTINGS.iter().find(|x| x.0 == *key).map_or(*key, |x| x.2);
+            config
+                .set(key, value, Path::new(""))
+                .map_err(|reason| Error::Config(None, format!("{}: {}", flag, reason)))?;
+            config.locations.remove(*key);
+        }
+        Ok(config)
+    }
+
+    pub fn from_env(flags: &Flags) -> Result<Self, Error> {
+        Self::load(flags, |x| env::var(x).ok())
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            Path::new("site/blogen.toml"),
+            "[site]
+            title = \"ldm0's Blog\"
+            language = \"en\"
+
+            [paths]
+            output = \"public\"
+            blogs = \"/var/blogs\"",
+        )
+        .unwrap();
+        assert_eq!(config.site.title, "ldm0's Blog");
+        assert_eq!(config.site.language, "en");
+        assert_eq!(config.site.author, "");
+        assert_eq!(config.output_dir, Path::new("site/public"));
+        assert_eq!(config.blog_dir, Path::new("/var/blogs"));
+        assert_eq!(config.tags_path, Path::new("assets/tags.txt"));
+
+        macro_rules! parse_error {
+            ($raw: expr, $message: expr) => {
+                assert_eq!(
+                    Config::parse(Path::new("blogen.toml"), $raw)
+                        .unwrap_err()
+                        .to_string(),
+                    $message
+                );
+            };
+        }
+        parse_error!(
+            "[site]\ntitle = 1",
+            "blogen.toml:2:1: invalid config: \"site.title\" should be a string."
+        );
+        parse_error!(
+            "[paths]\n\noutptu = \"public\"",
+            "blogen.toml:3:1: invalid config: unknown key \"paths.outptu\"."
+        );
+        parse_error!(
+            "title = \"a\"",
+            "blogen.toml:1:1: invalid config: \"title\" should be a table."
+        );
+        // Located in its own table, whatever the quoting
+        parse_error!(
+            "[site]\nstyle = \"css\"\n[highlight]\n  \"style\" = \"css\"",
+            "blogen.toml:4:3: invalid config: \"highlight.style\" should be \"inline\" or \"classes\", not \"css\"."
+        );
+        parse_error!(
+            "[site]\nstyle = 1\n['highlight']\nstyle = \"inline\"",
+            "blogen.toml:2:1: invalid config: \"site.style\" should be a string."
+        );
+        parse_error!(
+            "highlight.style = \"css\"",
+            "blogen.toml:1:1: invalid config: \"highlight.style\" should be \"inline\" or \"classes\", not \"css\"."
+        );
+        parse_error!(
+            "[build]\njobs = \"4\"",
+            "blogen.toml:2:1: invalid config: \"build.jobs\" should be a number."
+        );
+        parse_error!(
+            "[build]\njobs = -1",
+            "blogen.toml:2:1: invalid config: \"build.jobs\" should be a number, not \"-1\"."
+        );
+        parse_error!(
+            "[highlight]\nstyle = \"css\"",
+            "blogen.toml:2:1: invalid config: \"highlight.style\" should be \"inline\" or \"classes\", not \"css\"."
+        );
+        assert_eq!(
+            Config::parse(Path::new("blogen.toml"), "[build]\njobs = 4")
+                .unwrap()
+                .jobs,
+            4
+        );
+        let highlight = |raw| {
+            Config::parse(Path::new("blogen.toml"), raw)
+                .unwrap()
+                .highlight
+        };
+        assert_eq!(
+            highlight("[highlight]\ndark_theme = \"base16-ocean.dark\"").dark_theme,
+            Some("base16-ocean.dark".to_string())
+        );
+        assert_eq!(highlight("[highlight]\ndark_theme = \"\"").dark_theme, None);
+        assert_eq!(
+            highlight("[languages]\nmermaid = \"txt\"\n\"c++\" = \"cc\"").languages,
+            BTreeMap::from([
+                ("c++".to_string(), "cc".to_string()),
+                ("mermaid".to_string(), "txt".to_string())
+            ])
+        );
+        assert!(matches!(
+            Config::parse(Path::new("blogen.toml"), "[site\n"),
+            Err(Error::Config(..))
+        ));
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let args: Vec<String> = ["--output", "public", "--title=a b", "--config", "x.toml"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(
+            parse_flags(&args).unwrap(),
+            Flags {
+                config_path: Some(PathBuf::from("x.toml")),
+                settings: vec![
+                    ("paths.output", "public".to_string()),
+                    ("site.title", "a b".to_string())
+                ]
+            }
+        );
+        assert!(parse_flags(&["--outptu".to_string(), "x".to_string()]).is_err());
+        assert!(parse_flags(&["--output".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_load_overrides() {
+        let dir = env::temp_dir().join(format!("blogen-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("blogen.toml");
+        fs::write(
+            &path,
+            "[site]\ntitle = \"file\"\nauthor = \"file\"\n[paths]\noutput = \"public\"",
+        )
+        .unwrap();
+        let var = |name: &str| match name {
+            "SITE_TITLE" => Some("env".to_string()),
+            "OUTPUT_PATH" => Some("./output/".to_string()),
+            _ => None,
+        };
+        let mut flags = Flags {
+            config_path: Some(path),
+            settings: vec![("site.title", "flag".to_string())],
+        };
+        let config = Config::load(&flags, var).unwrap();
+        assert_eq!(config.site.title, "flag");
+        assert_eq!(config.site.author, "file");
+        assert_eq!(config.output_dir, Path::new("./output/"));
+        // Only what's left from the file is located in it
+        assert_eq!(config.locations.keys().collect::<Vec<_>>(), ["site.author"]);
+        assert_eq!(
+            config.locations["site.author"].to_string(),
+            format!("{}:3:1", dir.join("blogen.toml").display())
+        );
+        assert_eq!(
+            Config::load(&flags, |x| (x == "BLOGEN_JOBS").then(|| "x".to_string()))
+                .unwrap_err()
+                .to_string(),
+            "invalid config: BLOGEN_JOBS: \"build.jobs\" should be a number, not \"x\"."
+        );
+
+        // Only an explicit config file must exist
+        fs::remove_dir_all(&dir).unwrap();
+        flags.settings.clear();
+        assert!(matches!(Config::load(&flags, |_| None), Err(Error::Io(..))));
+    }
+}
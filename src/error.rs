@@ -0,0 +1,261 @@
+//! Errors found when generating the blog.
+//!
+//! Instead of panicking at the first problem, errors are gathered by a
+//! `Reporter` so one run tells everything that's wrong.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Where the problem is. Line and column are 1-based, they are absent when the
+// problem is about the whole file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Location {
+    pub fn file<P: AsRef<Path>>(path: P) -> Self {
+        Location {
+            path: path.as_ref().to_path_buf(),
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn new<P: AsRef<Path>>(path: P, line: usize, column: usize) -> Self {
+        Location {
+            path: path.as_ref().to_path_buf(),
+            line: Some(line),
+            column: Some(column),
+        }
+    }
+
+    // Locate the byte offset of `source`
+    pub fn at<P: AsRef<Path>>(path: P, source: &str, offset: usize) -> Self {
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        Location::new(path, line, column)
+    }
+
+    // Locate the first occurrence of `needle` in given line of `source`, fall
+    // back to the beginning of the line if not found.
+    pub fn find<P: AsRef<Path>>(path: P, source: &str, line: usize, needle: &str) -> Self {
+        let column = source
+            .lines()
+            .nth(line.saturating_sub(1))
+            .and_then(|x| x.find(needle).map(|i| x[..i].chars().count() + 1))
+            .unwrap_or(1);
+        Location::new(path, line, column)
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    // Metadata of a blog can't be parsed
+    Metadata(Location, String),
+    InvalidDate(Location, String),
+    UnknownTag(Location, String),
+    DuplicateTag(Location, String),
+    // Slug of a blog, and the blog already using it
+    DuplicateSlug(Location, String, PathBuf),
+    // Title in the blog and its file name
+    TitleMismatch(Location, String, String),
+    // Template is malformed
+    Template(Location, String),
+    // Blog content can't be rendered
+    Render(Location, String),
+    // No syntax for the language of a code block, it's left plain
+    UnknownLanguage(Location, String),
+    Io(Location, io::Error),
+    // Site configuration is invalid, located only if it's from the config file
+    Config(Option<Location>, String),
+}
+
+impl Error {
+    pub fn location(&self) -> Option<&Location> {
+        let location = match self {
+            Error::Metadata(location, _)
+            | Error::InvalidDate(location, _)
+            | Error::UnknownTag(location, _)
+            | Error::DuplicateTag(location, _)
+            | Error::DuplicateSlug(location, _, _)
+            | Error::TitleMismatch(location, _, _)
+            | Error::Template(location, _)
+            | Error::Render(location, _)
+            | Error::UnknownLanguage(location, _)
+            | Error::Io(location, _) => location,
+            Error::Config(location, _) => return location.as_ref(),
+        };
+        Some(location)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = self.location() {
+            write!(f, "{}: ", location)?;
+        }
+        match self {
+            Error::Metadata(_, reason) => write!(f, "invalid metadata: {}", reason),
+            Error::InvalidDate(_, date) => write!(f, "invalid date: {}", date),
+            Error::UnknownTag(_, tag) => write!(f, "unknown tag: {}", tag),
+            Error::DuplicateTag(_, tag) => write!(f, "duplicate tag: {}", tag),
+            Error::DuplicateSlug(_, slug, other) => write!(
+                f,
+                "duplicate slug \"{}\", already used by {}",
+                slug,
+                other.display()
+            ),
+            Error::TitleMismatch(_, title, file_name) => write!(
+                f,
+                "title \"{}\" doesn't correspond to file name \"{}\"",
+                title, file_name
+            ),
+            Error::Template(_, reason) => write!(f, "malformed template: {}", reason),
+            Error::Render(_, reason) => write!(f, "render failed: {}", reason),
+            Error::UnknownLanguage(_, lang) => write!(
+                f,
+                "unknown language \"{}\" of code block, left as plain text",
+                lang
+            ),
+            Error::Io(_, err) => write!(f, "{}", err),
+            Error::Config(_, reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Gathers errors, so we can report all of them at once. Warnings are printed
+// too, but they don't fail anything.
+#[derive(Debug, Default)]
+pub struct Reporter {
+    errors: Vec<Error>,
+    warnings: Vec<Error>,
+}
+
+impl Reporter {
+    pub fn new() -> Self {
+        Reporter {
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn report(&mut self, error: Error) {
+        self.errors.push(error);
+    }
+
+    pub fn warn(&mut self, warning: Error) {
+        self.warnings.push(warning);
+    }
+
+    // Report the error if any, return the value if ok.
+    pub fn check<T>(&mut self, result: Result<T, Error>) -> Option<T> {
+        match result {
+            Ok(x) => Some(x),
+            Err(err) => {
+                self.report(err);
+                None
+            }
+        }
+    }
+
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    // Print all warnings and errors to stderr, return if there is any error.
+    // Warnings are only printed once.
+    pub fn print(&mut self) -> bool {
+        for warning in self.warnings.drain(..) {
+            eprintln!("warning: {}", warning);
+        }
+        for error in self.errors() {
+            eprintln!("error: {}", error);
+        }
+        if self.has_errors() {
+            eprintln!("{} error(s) found.", self.errors.len());
+        }
+        self.has_errors()
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn test_location_display() {
+        assert_eq!("a/b.md", Location::file("a/b.md").to_string());
+        assert_eq!("a/b.md:3:7", Location::new("a/b.md", 3, 7).to_string());
+    }
+
+    #[test]
+    fn test_location_find() {
+        let source = "title\n2000/9/27\nlife | 工作 | fun\n";
+        assert_eq!(
+            Location::new("a", 3, 8),
+            Location::find("a", source, 3, "工作")
+        );
+        assert_eq!(
+            Location::new("a", 3, 13),
+            Location::find("a", source, 3, "fun")
+        );
+        assert_eq!(
+            Location::new("a", 3, 1),
+            Location::find("a", source, 3, "work")
+        );
+        assert_eq!(
+            Location::new("a", 9, 1),
+            Location::find("a", source, 9, "work")
+        );
+        assert_eq!(Location::new("a", 1, 1), Location::at("a", source, 0));
+        assert_eq!(
+            Location::new("a", 3, 13),
+            Location::at("a", source, source.find("fun").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_reporter() {
+        let mut reporter = Reporter::new();
+        assert_eq!(Some(1), reporter.check(Ok(1)));
+        assert!(!reporter.has_errors());
+        let error = Error::UnknownTag(Location::new("a.md", 3, 1), "lol".to_string());
+        assert_eq!("a.md:3:1: unknown tag: lol", error.to_string());
+        assert_eq!(None, reporter.check::<()>(Err(error)));
+        assert!(reporter.has_errors());
+        assert_eq!(1, reporter.errors().len());
+
+        reporter.warn(Error::UnknownLanguage(
+            Location::file("a.md"),
+            "tmol".to_string(),
+        ));
+        assert_eq!(1, reporter.warnings.len());
+        assert_eq!(1, reporter.errors().len());
+        assert!(reporter.print());
+        assert!(reporter.warnings.is_empty());
+    }
+}
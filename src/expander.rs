@@ -0,0 +1,1226 @@
+//! Generic expansion of HLF templates.
+//!
+//! A symbol can be bound to a collection by its definition, then it's repeated
+//! for every item of the collection, with the item as current context:
+//! ```txt
+//! <!--symbol--> blog chunk : blogs <!--symbol-->
+//! <!--content-->...<!--symbol--> tag <!--symbol-->...<!--content-->
+//! <!--symbol--> tag : blog.tags <!--symbol-->
+//! <!--content-->..._slot_of_tag_name...<!--content-->
+//! ```
+//! Symbols without binding are expanded once. The collections are:
+//! - `blogs`: all blogs, from new to old
+//! - `tags`: all tags, in the order of the tags file
+//! - `blog.tags`: tags of current blog
+//! - `tag.blogs`: blogs carrying current tag, from new to old
+//!
+//! A template extending a layout (see `hlf_parser`) gets all symbols of the
+//! layout, the ones it defines again are overridden. So the layout declares the
+//! skeleton with blocks like `head` and `body`, pages fill the blocks.
+//!
+//! Conditional sections (see `hlf_parser`) test a collection, which holds if
+//! not empty, or `blog.latex`, or a slot, which holds if not blank. Prefix `!`
+//! negates it, e.g. `<!--if--> !blog.tags <!--if-->`.
+//!
+//! Slots (see `slot`) are filled from the current context:
+//! - `blog.title`, `blog.year`, `blog.month`, `blog.day`, `blog.date`,
+//!   `blog.path`, `blog.slug`, `blog.preview`, `blog.content` (blog pages only)
+//!   and `blog.extra.<key>` for other metadata of the blog, empty if the blog
+//!   doesn't have it
+//! - `tag.name`, `tag.description`, `tag.path`
+//! - `site.blog_count`, `site.tag_count`, `site.root` (e.g. `../` in tag pages)
+//!   and `site.title`, `site.base_url`, `site.author`, `site.language` from
+//!   the config
+//!
+//! Each kind of page declares a `Schema`, templates are checked against it
+//! when loaded, so unknown slots, missing symbols and collections used where
+//! there's no blog or tag are found before anything is rendered.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::blog::Blog;
+use crate::blog_clusters::BlogClusters;
+use crate::config::Site;
+use crate::error::{Error, Location};
+use crate::hlf_parser::{parse_content, parse_document, HlfRhs, Symbol};
+use crate::shared::tag_path;
+use crate::slot::{fill_slots, parse_slots, Html, Segment, Slot, Value};
+use crate::tag::Tag;
+use crate::template_blog::extract_latex;
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Collection {
+    Blogs,
+    Tags,
+    BlogTags,
+    TagBlogs,
+}
+
+impl Collection {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "blogs" => Some(Collection::Blogs),
+            "tags" => Some(Collection::Tags),
+            "blog.tags" => Some(Collection::BlogTags),
+            "tag.blogs" => Some(Collection::TagBlogs),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Collection::Blogs => "blogs",
+            Collection::Tags => "tags",
+            Collection::BlogTags => "blog.tags",
+            Collection::TagBlogs => "tag.blogs",
+        }
+    }
+}
+
+// Slots of each scope besides `blog.extra.<key>`, see `Context::lookup`
+const BLOG_SLOTS: [&str; 9] = [
+    "title", "year", "month", "day", "date", "path", "slug", "preview", "content",
+];
+const TAG_SLOTS: [&str; 3] = ["name", "description", "path"];
+const SITE_SLOTS: [&str; 7] = [
+    "blog_count",
+    "tag_count",
+    "root",
+    "title",
+    "base_url",
+    "author",
+    "language",
+];
+
+// What the context has when a symbol is expanded, known when the template is
+// loaded
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Scope {
+    pub blog: bool,
+    pub tag: bool,
+    // Rendered content of the blog, only in blog pages
+    pub content: bool,
+}
+
+impl Scope {
+    // Scope of the collection items, like `Context::iter`
+    fn enter(self, collection: Collection) -> Result<Self, String> {
+        match collection {
+            Collection::Blogs => Ok(Scope {
+                blog: true,
+                content: false,
+                ..self
+            }),
+            Collection::Tags => Ok(Scope { tag: true, ..self }),
+            Collection::BlogTags if self.blog => Ok(Scope { tag: true, ..self }),
+            Collection::TagBlogs if self.tag => Ok(Scope {
+                blog: true,
+                content: false,
+                ..self
+            }),
+            Collection::BlogTags => Err("\"blog.tags\" is used without a blog.".into()),
+            Collection::TagBlogs => Err("\"tag.blogs\" is used without a tag.".into()),
+        }
+    }
+
+    // Check the slot is known, and filled in this scope if `filled`.
+    // Conditions don't need the latter, they are false without the blog or
+    // tag.
+    fn check_slot(self, name: &str, filled: bool) -> Result<(), String> {
+        let (scope, field) = name.split_once('.').unwrap_or((name, ""));
+        let (known, present) = match scope {
+            "blog" => (
+                BLOG_SLOTS.contains(&field)
+                    || field.strip_prefix("extra.").is_some_and(|x| !x.is_empty()),
+                self.blog,
+            ),
+            "tag" => (TAG_SLOTS.contains(&field), self.tag),
+            "site" => (SITE_SLOTS.contains(&field), true),
+            _ => (false, true),
+        };
+        if !known {
+            Err(format!("unknown slot \"{}\".", name))
+        } else if filled && !present {
+            Err(format!("\"{}\" is used without a {}.", name, scope))
+        } else if filled && name == "blog.content" && !self.content {
+            Err("\"blog.content\" is only available in blog pages.".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// What a kind of page needs from its template
+pub struct Schema {
+    // Symbols the template must define
+    pub symbols: &'static [&'static str],
+    // Collections the page must list somewhere
+    pub collections: &'static [Collection],
+    // Scope `main` is expanded in
+    pub scope: Scope,
+}
+
+// What the slots are filled with
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    pub clusters: &'a BlogClusters,
+    pub blog: Option<&'a Blog>,
+    pub tag: Option<&'a Tag>,
+    // Rendered html of the blog content
+    pub content: Option<&'a str>,
+    // Relative path from the page to the output directory
+    pub root: &'a str,
+    pub site: Option<&'a Site>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(clusters: &'a BlogClusters) -> Self {
+        Context {
+            clusters,
+            blog: None,
+            tag: None,
+            content: None,
+            root: "./",
+            site: None,
+        }
+    }
+
+    pub fn with_blog(self, blog: &'a Blog) -> Self {
+        Context {
+            blog: Some(blog),
+            content: None,
+            ..self
+        }
+    }
+
+    pub fn with_content(self, content: &'a str) -> Self {
+        Context {
+            content: Some(content),
+            ..self
+        }
+    }
+
+    pub fn with_root(self, root: &'a str) -> Self {
+        Context { root, ..self }
+    }
+
+    pub fn with_site(self, site: &'a Site) -> Self {
+        Context {
+            site: Some(site),
+            ..self
+        }
+    }
+
+    pub fn with_tag(self, tag: &'a Tag) -> Self {
+        Context {
+            tag: Some(tag),
+            ..self
+        }
+    }
+
+    // Contexts of the collection items
+    fn iter(&self, collection: Collection) -> Result<Vec<Context<'a>>, String> {
+        let clusters = self.clusters;
+        Ok(match collection {
+            Collection::Blogs => clusters
+                .get_blogs()
+                .iter()
+                .map(|x| self.with_blog(x))
+                .collect(),
+            Collection::Tags => clusters
+                .get_tags()
+                .iter()
+                .map(|x| self.with_tag(x))
+                .collect(),
+            Collection::BlogTags => self
+                .blog
+                .ok_or("\"blog.tags\" is used without a blog.")?
+                .tags
+                .iter()
+                .map(|x| self.with_tag(clusters.get_tag(*x).unwrap()))
+                .collect(),
+            Collection::TagBlogs => {
+                let tag = self.tag.ok_or("\"tag.blogs\" is used without a tag.")?;
+                clusters
+                    .get_tag_blogs(*clusters.get_tag_handle(&tag.name).unwrap())
+                    .unwrap()
+                    .iter()
+                    .map(|x| self.with_blog(clusters.get_blog(*x).unwrap()))
+                    .collect()
+            }
+        })
+    }
+
+    // A collection holds if it's not empty, `blog.latex` holds if the blog has
+    // LaTeX, other names are slots, which hold if not blank. Nothing holds
+    // without the blog or tag it's about.
+    fn test(&self, condition: &Condition) -> Result<bool, String> {
+        let name = condition.name.as_str();
+        let absent = match name.split_once('.') {
+            Some(("blog", _)) => self.blog.is_none(),
+            Some(("tag", _)) => self.tag.is_none(),
+            _ => false,
+        };
+        let holds = match (Collection::from_name(name), self.blog) {
+            _ if absent => false,
+            (Some(collection), _) => !self.iter(collection)?.is_empty(),
+            (None, Some(blog)) if name == "blog.latex" => {
+                !extract_latex(&blog.content).1.is_empty()
+            }
+            (None, _) => match self.lookup(name)? {
+                Value::Text(x) => !x.trim().is_empty(),
+                Value::Date(..) => true,
+            },
+        };
+        Ok(holds != condition.negated)
+    }
+
+    // Value of the slot, e.g. `blog.title`
+    fn lookup(&self, name: &str) -> Result<Value<'a>, String> {
+        let text = |x: &'a str| Ok(Value::Text(Cow::Borrowed(x)));
+        let number = |x: u16| Ok(Value::Text(Cow::Owned(x.to_string())));
+        let (scope, field) = name.split_once('.').unwrap_or((name, ""));
+        match scope {
+            "blog" => {
+                let blog = self
+                    .blog
+                    .ok_or_else(|| format!("\"{}\" is used without a blog.", name))?;
+                match field {
+                    "title" => text(&blog.title),
+                    "year" => number(blog.year),
+                    "month" => number(blog.month),
+                    "day" => number(blog.day),
+                    "date" => Ok(Value::Date(blog.year, blog.month, blog.day)),
+                    "path" => Ok(Value::Text(Cow::Owned(blog.path()))),
+                    "slug" => text(&blog.slug),
+                    "preview" => text(&blog.preview),
+                    "content" => self
+                        .content
+                        .map(|x| Value::Text(Cow::Borrowed(x)))
+                        .ok_or_else(|| "\"blog.content\" is only available in blog pages.".into()),
+                    // Metadata of the blog we don't know
+                    _ => match field.strip_prefix("extra.") {
+                        Some(key) => text(blog.extra.get(key).map_or("", |x| x.as_str())),
+                        None => Err(format!("unknown slot \"{}\".", name)),
+                    },
+                }
+            }
+            "tag" => {
+                let tag = self
+                    .tag
+                    .ok_or_else(|| format!("\"{}\" is used without a tag.", name))?;
+                match field {
+                    "name" => text(&tag.name),
+                    "description" => text(&tag.description),
+                    "path" => Ok(Value::Text(Cow::Owned(tag_path(&tag.name)))),
+                    _ => Err(format!("unknown slot \"{}\".", name)),
+                }
+            }
+            "site" => match field {
+                "blog_count" => Ok(Value::Text(Cow::Owned(
+                    self.clusters.num_blog().to_string(),
+                ))),
+                "tag_count" => Ok(Value::Text(Cow::Owned(self.clusters.num_tag().to_string()))),
+                "root" => text(self.root),
+                // Empty without config
+                "title" => text(self.site.map_or("", |x| x.title.as_str())),
+                "base_url" => text(self.site.map_or("", |x| x.base_url.as_str())),
+                "author" => text(self.site.map_or("", |x| x.author.as_str())),
+                "language" => text(self.site.map_or("", |x| x.language.as_str())),
+                _ => Err(format!("unknown slot \"{}\".", name)),
+            },
+            _ => Err(format!("unknown slot \"{}\".", name)),
+        }
+    }
+}
+
+// `name`, or `!name` for negation
+struct Condition {
+    name: String,
+    negated: bool,
+    location: Location,
+}
+
+impl Condition {
+    fn parse(s: &str, location: Location) -> Result<Self, String> {
+        let (name, negated) = match s.strip_prefix('!') {
+            Some(x) => (x.trim(), true),
+            None => (s, false),
+        };
+        if name.is_empty() {
+            return Err(format!("empty condition \"{}\".", s));
+        }
+        Ok(Condition {
+            name: name.to_string(),
+            negated,
+            location,
+        })
+    }
+}
+
+// Right side of a symbol, with slots parsed. Slots and symbols used are
+// located for errors.
+enum Part {
+    // Locations of the slots in order
+    Text(Vec<Segment>, Vec<Location>),
+    Symbol(String, Location),
+    If(Condition, Vec<Part>, Vec<Part>),
+}
+
+// State of `Expander::validate` walking the symbols
+struct Walk<'a> {
+    stack: Vec<&'a str>,
+    checked: HashSet<(&'a str, Scope)>,
+    listed: HashSet<Collection>,
+}
+
+pub struct Expander {
+    // Template loaded
+    path: PathBuf,
+    hlfs: HashMap<String, Vec<Part>>,
+    bindings: HashMap<String, Collection>,
+    // Layouts and partials read besides the template
+    files: Vec<PathBuf>,
+}
+
+// Locations of the slots in the segments
+fn slot_locations<'a>(
+    segments: &'a [Segment],
+    locations: &'a [Location],
+) -> impl Iterator<Item = (&'a Slot, &'a Location)> {
+    segments
+        .iter()
+        .filter_map(|x| match x {
+            Segment::Slot(slot) => Some(slot),
+            Segment::Text(_) => None,
+        })
+        .zip(locations)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Location of `part`, which is sliced from `raw` read from `path`
+fn locate(path: &Path, raw: &str, part: &str) -> Location {
+    let offset = (part.as_ptr() as usize).wrapping_sub(raw.as_ptr() as usize);
+    if offset <= raw.len() {
+        Location::at(path, raw, offset)
+    } else {
+        Location::file(path)
+    }
+}
+
+// Parse slots of the right side parsed from `raw`, partials included are read
+// and parsed in place. `includes` is the chain of files including the current
+// one, partials read are recorded in `files`.
+fn compile(
+    path: &Path,
+    raw: &str,
+    lhs: &str,
+    rhs: HlfRhs,
+    includes: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    parts: &mut Vec<Part>,
+) -> Result<(), Error> {
+    let invalid = |part: &str, reason: String| {
+        Error::Template(
+            locate(path, raw, part),
+            format!("in \"{}\" symbol, {}", lhs, reason),
+        )
+    };
+    // Conditions not ended, with parts before `<!--else-->` if met, and parts
+    // of the sections, the first one is outside conditions. Parser ensures
+    // conditions are balanced in a file.
+    let mut conditions: Vec<(Condition, Option<Vec<Part>>)> = Vec::new();
+    let mut sections: Vec<Vec<Part>> = vec![std::mem::take(parts)];
+    for symbol in rhs {
+        let parts = sections.last_mut().unwrap();
+        match symbol {
+            Symbol::T(x) => {
+                let segments =
+                    parse_slots(x).map_err(|(offset, reason)| invalid(&x[offset..], reason))?;
+                let locations = segments
+                    .iter()
+                    .filter_map(|segment| match segment {
+                        Segment::Slot(slot) => Some(locate(path, raw, &x[slot.offset..])),
+                        Segment::Text(_) => None,
+                    })
+                    .collect();
+                parts.push(Part::Text(segments, locations));
+            }
+            Symbol::N(x) => parts.push(Part::Symbol(x.to_string(), locate(path, raw, x))),
+            Symbol::If(x) => {
+                let condition = Condition::parse(x, locate(path, raw, x))
+                    .map_err(|reason| invalid(x, reason))?;
+                conditions.push((condition, None));
+                sections.push(Vec::new());
+            }
+            Symbol::Else => {
+                conditions.last_mut().unwrap().1 = sections.pop();
+                sections.push(Vec::new());
+            }
+            Symbol::EndIf => {
+                let last = sections.pop().unwrap();
+                let (condition, then) = conditions.pop().unwrap();
+                let (then, otherwise) = match then {
+                    Some(then) => (then, last),
+                    None => (last, Vec::new()),
+                };
+                sections
+                    .last_mut()
+                    .unwrap()
+                    .push(Part::If(condition, then, otherwise));
+            }
+            Symbol::I(x) => {
+                // Relative to the file including it
+                let partial_path = path.parent().unwrap_or(Path::new("")).join(x);
+                let partial = canonical(&partial_path);
+                if includes.contains(&partial) {
+                    let chain: Vec<String> = includes
+                        .iter()
+                        .chain([&partial])
+                        .map(|x| x.display().to_string())
+                        .collect();
+                    return Err(Error::Template(
+                        locate(path, raw, x),
+                        format!("include cycle: {}", chain.join(" -> ")),
+                    ));
+                }
+                let partial_raw = fs::read_to_string(&partial_path)
+                    .map_err(|err| Error::Io(Location::file(&partial_path), err))?;
+                let partial_rhs = parse_content(&partial_raw).map_err(|err| {
+                    Error::Template(
+                        Location::new(&partial_path, err.line, err.column),
+                        err.kind.to_string(),
+                    )
+                })?;
+                if !files.contains(&partial_path) {
+                    files.push(partial_path.clone());
+                }
+                includes.push(partial);
+                compile(
+                    &partial_path,
+                    &partial_raw,
+                    lhs,
+                    partial_rhs,
+                    includes,
+                    files,
+                    parts,
+                )?;
+                includes.pop();
+            }
+        }
+    }
+    *parts = sections.pop().unwrap();
+    Ok(())
+}
+
+impl Expander {
+    // Parse the template into a symbol table, partials are included
+    pub fn load(template_path: &Path, template_raw: &str) -> Result<Self, Error> {
+        Self::load_layer(template_path, template_raw, &mut Vec::new())
+    }
+
+    // Symbols of the template override symbols of the layout it extends.
+    // `layouts` is the chain of templates extending the current one.
+    fn load_layer(
+        template_path: &Path,
+        template_raw: &str,
+        layouts: &mut Vec<PathBuf>,
+    ) -> Result<Self, Error> {
+        let document = parse_document(template_raw).map_err(|err| {
+            Error::Template(
+                Location::new(template_path, err.line, err.column),
+                err.kind.to_string(),
+            )
+        })?;
+        let invalid = |part: &str, reason: String| {
+            Error::Template(locate(template_path, template_raw, part), reason)
+        };
+        let mut expander = match document.extends {
+            Some(x) => {
+                let layout_path = template_path.parent().unwrap_or(Path::new("")).join(x);
+                let layout = canonical(&layout_path);
+                layouts.push(canonical(template_path));
+                if layouts.contains(&layout) {
+                    let chain: Vec<String> = layouts
+                        .iter()
+                        .chain([&layout])
+                        .map(|x| x.display().to_string())
+                        .collect();
+                    return Err(invalid(x, format!("extends cycle: {}", chain.join(" -> "))));
+                }
+                let layout_raw = fs::read_to_string(&layout_path)
+                    .map_err(|err| Error::Io(Location::file(&layout_path), err))?;
+                let mut layout = Self::load_layer(&layout_path, &layout_raw, layouts)?;
+                layout.files.push(layout_path);
+                layout
+            }
+            None => Expander {
+                path: PathBuf::new(),
+                hlfs: HashMap::new(),
+                bindings: HashMap::new(),
+                files: Vec::new(),
+            },
+        };
+        // Symbols defined in this template, the layout's could be overridden
+        let mut defined = HashSet::new();
+        for i in document.hlfs.into_iter() {
+            // `name : collection`
+            let (lhs, binding) = match i.lhs.split_once(':') {
+                Some((lhs, collection)) => {
+                    let lhs = lhs.trim().to_string();
+                    match Collection::from_name(collection.trim()) {
+                        Some(x) => (lhs, Some(x)),
+                        None => {
+                            return Err(invalid(
+                                collection.trim(),
+                                format!(
+                                    "unknown collection \"{}\" of \"{}\" symbol.",
+                                    collection.trim(),
+                                    lhs
+                                ),
+                            ))
+                        }
+                    }
+                }
+                None => (i.lhs.to_string(), None),
+            };
+            if !defined.insert(lhs.clone()) {
+                return Err(invalid(
+                    i.lhs,
+                    format!("\"{}\" symbol is defined twice.", lhs),
+                ));
+            }
+            let mut parts = Vec::new();
+            let mut includes = vec![canonical(template_path)];
+            compile(
+                template_path,
+                template_raw,
+                &lhs,
+                i.rhs,
+                &mut includes,
+                &mut expander.files,
+                &mut parts,
+            )?;
+            match binding {
+                Some(x) => expander.bindings.insert(lhs.clone(), x),
+                None => expander.bindings.remove(&lhs),
+            };
+            expander.hlfs.insert(lhs, parts);
+        }
+        expander.path = template_path.to_path_buf();
+        Ok(expander)
+    }
+
+    // Load and check the template against the schema
+    pub fn load_for(
+        template_path: &Path,
+        template_raw: &str,
+        schema: &Schema,
+    ) -> Result<Self, Error> {
+        let expander = Self::load(template_path, template_raw)?;
+        expander.validate(schema)?;
+        Ok(expander)
+    }
+
+    // Layouts and partials the template is made of, for watching
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    // Error at the slot or symbol used, or the template if not known
+    fn invalid(&self, location: Option<&Location>, reason: String) -> Error {
+        let location = location.cloned();
+        Error::Template(
+            location.unwrap_or_else(|| Location::file(&self.path)),
+            reason,
+        )
+    }
+
+    // Walk symbols from `main` like `expand` does, but with scopes instead of
+    // contexts, so every symbol is checked in each scope it's expanded in.
+    fn validate(&self, schema: &Schema) -> Result<(), Error> {
+        let invalid = |reason: String| self.invalid(None, reason);
+        let missing: Vec<String> = schema
+            .symbols
+            .iter()
+            .filter(|x| !self.hlfs.contains_key(**x))
+            .map(|x| format!("\"{}\"", x))
+            .collect();
+        if !missing.is_empty() {
+            return Err(invalid(format!("missing {} symbol.", missing.join(", "))));
+        }
+        let mut walk = Walk {
+            stack: Vec::new(),
+            checked: HashSet::new(),
+            listed: HashSet::new(),
+        };
+        self.check_symbol("main", None, schema.scope, &mut walk)?;
+        match schema
+            .collections
+            .iter()
+            .find(|x| !walk.listed.contains(*x))
+        {
+            Some(x) => Err(invalid(format!("no symbol is bound to \"{}\".", x.name()))),
+            None => Ok(()),
+        }
+    }
+
+    // `used` is where the symbol is used, None for `main`
+    fn check_symbol<'a>(
+        &'a self,
+        lhs: &'a str,
+        used: Option<&Location>,
+        scope: Scope,
+        walk: &mut Walk<'a>,
+    ) -> Result<(), Error> {
+        let parts = self
+            .hlfs
+            .get(lhs)
+            .ok_or_else(|| self.invalid(used, format!("\"{}\" symbol not found.", lhs)))?;
+        if walk.stack.contains(&lhs) {
+            return Err(self.invalid(used, format!("\"{}\" symbol expands to itself.", lhs)));
+        }
+        let scope = match self.bindings.get(lhs) {
+            Some(collection) => {
+                walk.listed.insert(*collection);
+                scope
+                    .enter(*collection)
+                    .map_err(|x| self.invalid(used, format!("in \"{}\" symbol, {}", lhs, x)))?
+            }
+            None => scope,
+        };
+        if !walk.checked.insert((lhs, scope)) {
+            return Ok(());
+        }
+        walk.stack.push(lhs);
+        self.check_parts(lhs, parts, scope, walk)?;
+        walk.stack.pop();
+        Ok(())
+    }
+
+    fn check_parts<'a>(
+        &'a self,
+        lhs: &'a str,
+        parts: &'a [Part],
+        scope: Scope,
+        walk: &mut Walk<'a>,
+    ) -> Result<(), Error> {
+        let invalid = |location: &Location, x: String| {
+            Error::Template(location.clone(), format!("in \"{}\" symbol, {}", lhs, x))
+        };
+        for part in parts {
+            match part {
+                Part::Text(segments, locations) => {
+                    for (slot, location) in slot_locations(segments, locations) {
+                        scope
+                            .check_slot(&slot.name, true)
+                            .map_err(|x| invalid(location, x))?;
+                    }
+                }
+                Part::Symbol(x, location) => self.check_symbol(x, Some(location), scope, walk)?,
+                Part::If(condition, then, otherwise) => {
+                    let name = condition.name.as_str();
+                    if Collection::from_name(name).is_none() && name != "blog.latex" {
+                        scope
+                            .check_slot(name, false)
+                            .map_err(|x| invalid(&condition.location, x))?;
+                    }
+                    self.check_parts(lhs, then, scope, walk)?;
+                    self.check_parts(lhs, otherwise, scope, walk)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Expand the `main` symbol
+    pub fn expand(&self, context: Context) -> Result<String, Error> {
+        let mut result = Html::default();
+        let mut stack = Vec::new();
+        self.expand_symbol("main", None, context, &mut stack, &mut result)?;
+        Ok(result.text)
+    }
+
+    // `used` is where the symbol is used, None for `main`
+    fn expand_symbol<'a>(
+        &'a self,
+        lhs: &'a str,
+        used: Option<&Location>,
+        context: Context,
+        stack: &mut Vec<&'a str>,
+        result: &mut Html,
+    ) -> Result<(), Error> {
+        let rhs = self
+            .hlfs
+            .get(lhs)
+            .ok_or_else(|| self.invalid(used, format!("\"{}\" symbol not found.", lhs)))?;
+        if stack.contains(&lhs) {
+            return Err(self.invalid(used, format!("\"{}\" symbol expands to itself.", lhs)));
+        }
+        let contexts = match self.bindings.get(lhs) {
+            Some(collection) => context
+                .iter(*collection)
+                .map_err(|reason| self.invalid(used, reason))?,
+            None => vec![context],
+        };
+        stack.push(lhs);
+        for context in contexts {
+            self.expand_parts(rhs, context, stack, result)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    fn expand_parts<'a>(
+        &'a self,
+        parts: &'a [Part],
+        context: Context,
+        stack: &mut Vec<&'a str>,
+        result: &mut Html,
+    ) -> Result<(), Error> {
+        for part in parts {
+            match part {
+                Part::Text(segments, locations) => {
+                    fill_slots(segments, |name| context.lookup(name), result).map_err(
+                        |(offset, reason)| {
+                            let location = slot_locations(segments, locations)
+                                .find(|(slot, _)| slot.offset == offset)
+                                .map(|(_, location)| location);
+                            self.invalid(location, reason)
+                        },
+                    )?
+                }
+                Part::Symbol(x, location) => {
+                    self.expand_symbol(x, Some(location), context, stack, result)?
+                }
+                Part::If(condition, then, otherwise) => {
+                    let holds = context
+                        .test(condition)
+                        .map_err(|reason| self.invalid(Some(&condition.location), reason))?;
+                    if holds {
+                        self.expand_parts(then, context, stack, result)?
+                    } else {
+                        self.expand_parts(otherwise, context, stack, result)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod expander_tests {
+    use super::*;
+    use crate::error::Reporter;
+    use std::path::PathBuf;
+
+    fn clusters() -> BlogClusters {
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_tags(
+            Path::new("tags.txt"),
+            "life
+            things about current life
+
+            work
+            about my works",
+            &mut reporter,
+        );
+        clusters.add_blogs(
+            &[
+                (
+                    PathBuf::from("old-blog.md"),
+                    "Old Blog\n2000/9/27\nlife | work\n---\npreview\n---\ncontent".to_string(),
+                ),
+                (
+                    PathBuf::from("new-blog.md"),
+                    "New Blog\n2001/9/27\nwork\n---\npreview\n---\ncontent".to_string(),
+                ),
+            ],
+            &mut reporter,
+        );
+        assert!(!reporter.has_errors());
+        clusters
+    }
+
+    #[test]
+    fn test_expand_nested_collections() {
+        let expander = Expander::load(
+            Path::new("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content-->[<!--symbol-->blog<!--symbol-->]<!--content-->
+            <!--symbol-->blog : blogs<!--symbol--><!--content-->_slot_of_blog_title(<!--symbol-->tag<!--symbol-->)<!--content-->
+            <!--symbol-->tag:blog.tags<!--symbol--><!--content-->_slot_of_tag_name;<!--content-->",
+        )
+        .unwrap();
+        let clusters = clusters();
+        assert_eq!(
+            expander.expand(Context::new(&clusters)).unwrap(),
+            "[New Blog(work;)Old Blog(life;work;)]"
+        );
+    }
+
+    #[test]
+    fn test_expand_loop_anywhere() {
+        // Loops could be placed in any symbol, even main
+        let expander = Expander::load(
+            Path::new("template.html"),
+            "<!--symbol-->main : tags<!--symbol--><!--content-->_slot_of_tag_name:<!--symbol-->blog<!--symbol-->;<!--content-->
+            <!--symbol-->blog : tag.blogs<!--symbol--><!--content--> _slot_of_blog_path<!--content-->",
+        )
+        .unwrap();
+        let clusters = clusters();
+        assert_eq!(
+            expander.expand(Context::new(&clusters)).unwrap(),
+            "life: old-blog.html;work: new-blog.html old-blog.html;"
+        );
+    }
+
+    #[test]
+    fn test_expand_slots() {
+        let expander = Expander::load(
+            Path::new("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content-->{{site.blog_count}}:<!--symbol-->blog<!--symbol--><!--content-->
+            <!--symbol-->blog : blogs<!--symbol--><!--content-->{{ blog.date | date \"%m-%d\" }} {{ blog.title | upper }} _slot_of_blog_year;<!--content-->",
+        )
+        .unwrap();
+        let clusters = clusters();
+        assert_eq!(
+            expander.expand(Context::new(&clusters)).unwrap(),
+            "2:09-27 NEW BLOG 2001;09-27 OLD BLOG 2000;"
+        );
+        assert!(Expander::load(
+            Path::new("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content-->{{ blog.title | shout }}<!--content-->"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_expand_conditions() {
+        let expander = Expander::load(
+            Path::new("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content--><!--if-->!blogs<!--if-->none<!--else--><!--symbol-->blog<!--symbol--><!--endif--><!--content-->
+            <!--symbol-->blog : blogs<!--symbol--><!--content-->{{ blog.title }}<!--if-->blog.latex<!--if-->$<!--endif-->(<!--if-->tag.name<!--if-->?<!--else--><!--if-->blog.preview<!--if--><!--symbol-->tag<!--symbol--><!--endif--><!--endif-->)<!--content-->
+            <!--symbol-->tag : blog.tags<!--symbol--><!--content-->{{ tag.name }};<!--content-->",
+        )
+        .unwrap();
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_tags(Path::new("tags.txt"), "life\nthings", &mut reporter);
+        clusters.add_blogs(
+            &[
+                (
+                    PathBuf::from("math.md"),
+                    "Math\n2000/9/27\nlife\n---\npreview\n---\n$$x$$".to_string(),
+                ),
+                (
+                    PathBuf::from("blank.md"),
+                    "Blank\n2001/9/27\nlife\n---\n  \n---\ncontent".to_string(),
+                ),
+            ],
+            &mut reporter,
+        );
+        assert!(!reporter.has_errors());
+        assert_eq!(
+            expander.expand(Context::new(&clusters)).unwrap(),
+            "Blank()Math$(life;)"
+        );
+        assert_eq!(
+            expander.expand(Context::new(&BlogClusters::new())).unwrap(),
+            "none"
+        );
+        assert_eq!(
+            expander
+                .expand(Context::new(&clusters).with_tag(clusters.get_tag(0).unwrap()))
+                .unwrap(),
+            "Blank(?)Math$(?)"
+        );
+    }
+
+    #[test]
+    fn test_expand_include() {
+        let dir = std::env::temp_dir().join(format!("blogen-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        fs::write(
+            dir.join("partials/nav.html"),
+            "<nav>{{ site.root }}<!--include-->footer.html<!--include--></nav>",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("partials/footer.html"),
+            "{{ site.tag_count }} tags",
+        )
+        .unwrap();
+        // a includes b includes a
+        fs::write(
+            dir.join("partials/a.html"),
+            "<!--include-->b.html<!--include-->",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("partials/b.html"),
+            "<!--include-->a.html<!--include-->",
+        )
+        .unwrap();
+
+        let clusters = clusters();
+        let expander = Expander::load(
+            &dir.join("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content-->[<!--include-->partials/nav.html<!--include-->]<!--content-->",
+        )
+        .unwrap();
+        assert_eq!(
+            expander.expand(Context::new(&clusters)).unwrap(),
+            "[<nav>./2 tags</nav>]"
+        );
+        let err = Expander::load(
+            &dir.join("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content--><!--include-->partials/a.html<!--include--><!--content-->",
+        )
+        .err()
+        .unwrap();
+        assert!(
+            matches!(&err, Error::Template(_, reason) if reason.starts_with("include cycle: "))
+        );
+        assert_eq!(err.location().unwrap().path, dir.join("partials/b.html"));
+        assert!(matches!(
+            Expander::load(
+                &dir.join("template.html"),
+                "<!--symbol-->main<!--symbol--><!--content--><!--include-->nope.html<!--include--><!--content-->",
+            ),
+            Err(Error::Io(..))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_extends() {
+        let dir = std::env::temp_dir().join(format!("blogen-extends-{}", std::process::id()));
+        fs::create_dir_all(dir.join("layouts")).unwrap();
+        fs::write(
+            dir.join("layouts/base.html"),
+            "<!--symbol-->main<!--symbol--><!--content--><head><!--symbol-->head<!--symbol--></head><!--symbol-->body<!--symbol--><!--content-->
+            <!--symbol-->head<!--symbol--><!--content-->default<!--content-->
+            <!--symbol-->body<!--symbol--><!--content-->nothing<!--content-->",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("layouts/list.html"),
+            "<!--extends-->base.html<!--extends-->
+            <!--symbol-->body<!--symbol--><!--content-->[<!--symbol-->item<!--symbol-->]<!--content-->
+            <!--symbol-->item : tags<!--symbol--><!--content-->{{ tag.name }};<!--content-->",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("layouts/a.html"),
+            "<!--extends-->b.html<!--extends-->",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("layouts/b.html"),
+            "<!--extends-->a.html<!--extends-->",
+        )
+        .unwrap();
+
+        let clusters = clusters();
+        let expander = Expander::load(
+            &dir.join("template.html"),
+            "<!--extends-->layouts/list.html<!--extends-->
+            <!--symbol-->item : blogs<!--symbol--><!--content-->{{ blog.title }};<!--content-->",
+        )
+        .unwrap();
+        assert_eq!(
+            expander.expand(Context::new(&clusters)).unwrap(),
+            "<head>default</head>[New Blog;Old Blog;]"
+        );
+        let err = Expander::load(
+            &dir.join("template.html"),
+            "<!--extends-->layouts/a.html<!--extends-->",
+        )
+        .err()
+        .unwrap();
+        assert!(
+            matches!(&err, Error::Template(_, reason) if reason.starts_with("extends cycle: "))
+        );
+        let err = Expander::load(
+            &dir.join("template.html"),
+            "<!--extends-->layouts/list.html<!--extends-->
+            <!--symbol-->head<!--symbol--><!--content--><!--content-->
+            <!--symbol-->head<!--symbol--><!--content--><!--content-->",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}:3:26: malformed template: \"head\" symbol is defined twice.",
+                dir.join("template.html").display()
+            )
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        const SCHEMA: Schema = Schema {
+            symbols: &["main", "tags"],
+            collections: &[Collection::Blogs],
+            scope: Scope {
+                blog: false,
+                tag: false,
+                content: false,
+            },
+        };
+        let check = |template: &str| {
+            Expander::load(Path::new("template.html"), template)
+                .unwrap()
+                .validate(&SCHEMA)
+        };
+        let validate = |template: &str| {
+            check(template).map_err(|err| match err {
+                Error::Template(_, reason) => reason,
+                _ => unreachable!(),
+            })
+        };
+        let blogs = "<!--symbol-->blog : blogs<!--symbol--><!--content-->{{ blog.title }}<!--if-->!blog.extra.mood<!--if--><!--symbol-->tags<!--symbol--><!--endif--><!--content-->
+            <!--symbol-->tags<!--symbol--><!--content--><!--symbol-->tag<!--symbol--><!--content-->
+            <!--symbol-->tag : blog.tags<!--symbol--><!--content-->{{ tag.name }}<!--content-->";
+        assert_eq!(
+            validate(&format!(
+                "<!--symbol-->main<!--symbol--><!--content-->{{{{ site.root }}}}<!--if-->tag.name<!--if--><!--endif--><!--symbol-->blog<!--symbol--><!--content-->{}",
+                blogs
+            )),
+            Ok(())
+        );
+        assert_eq!(
+            validate("<!--symbol-->a<!--symbol--><!--content--><!--content-->"),
+            Err("missing \"main\", \"tags\" symbol.".to_string())
+        );
+        assert_eq!(
+            validate(&format!(
+                "<!--symbol-->main<!--symbol--><!--content--><!--content-->{}",
+                blogs
+            )),
+            Err("no symbol is bound to \"blogs\".".to_string())
+        );
+        macro_rules! invalid {
+            ($main: expr, $reason: expr) => {
+                assert_eq!(
+                    validate(&format!(
+                        "<!--symbol-->main<!--symbol--><!--content-->{}<!--symbol-->blog<!--symbol--><!--content-->{}",
+                        $main, blogs
+                    )),
+                    Err($reason.to_string())
+                );
+            };
+        }
+        invalid!(
+            "_slot_of_blog_titel",
+            "in \"main\" symbol, unknown slot \"blog.titel\"."
+        );
+        invalid!(
+            "<!--if-->tag.nmae<!--if--><!--endif-->",
+            "in \"main\" symbol, unknown slot \"tag.nmae\"."
+        );
+        invalid!(
+            "{{ tag.name }}",
+            "in \"main\" symbol, \"tag.name\" is used without a tag."
+        );
+        invalid!(
+            "<!--symbol-->tags<!--symbol-->",
+            "in \"tag\" symbol, \"blog.tags\" is used without a blog."
+        );
+        invalid!(
+            "<!--symbol-->main<!--symbol-->",
+            "\"main\" symbol expands to itself."
+        );
+        assert_eq!(
+            validate(&format!(
+                "<!--symbol-->main<!--symbol--><!--content--><!--symbol-->blog<!--symbol--><!--content-->{}",
+                blogs.replace("{{ blog.title }}", "{{ blog.content }}")
+            )),
+            Err("in \"blog\" symbol, \"blog.content\" is only available in blog pages.".to_string())
+        );
+
+        // Located at the slot or symbol used
+        let located = |main: &str| {
+            let err = check(&format!(
+                "<!--symbol-->main<!--symbol--><!--content-->\n{}<!--symbol-->blog<!--symbol--><!--content-->{}",
+                main, blogs
+            ))
+            .unwrap_err();
+            err.location().unwrap().to_string()
+        };
+        assert_eq!(located("<p>\n  {{ blog.titel }}"), "template.html:3:3");
+        assert_eq!(
+            located("<!--if-->tag.nmae<!--if--><!--endif-->"),
+            "template.html:2:10"
+        );
+        assert_eq!(
+            located("x<!--symbol-->main<!--symbol-->"),
+            "template.html:2:15"
+        );
+        assert_eq!(
+            check("<!--symbol-->a<!--symbol--><!--content--><!--content-->")
+                .unwrap_err()
+                .location()
+                .unwrap()
+                .to_string(),
+            "template.html"
+        );
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let clusters = clusters();
+        let expand = |template: &str| match Expander::load(Path::new("template.html"), template)
+            .unwrap()
+            .expand(Context::new(&clusters))
+            .unwrap_err()
+        {
+            Error::Template(_, reason) => reason,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            expand("<!--symbol-->a<!--symbol--><!--content--><!--content-->"),
+            "\"main\" symbol not found."
+        );
+        assert_eq!(
+            expand("<!--symbol-->main<!--symbol--><!--content--><!--symbol-->tag<!--symbol--><!--content-->
+                <!--symbol-->tag : blog.tags<!--symbol--><!--content--><!--content-->"),
+            "\"blog.tags\" is used without a blog."
+        );
+        assert_eq!(
+            expand("<!--symbol-->main<!--symbol--><!--content--><!--symbol-->main<!--symbol--><!--content-->"),
+            "\"main\" symbol expands to itself."
+        );
+        assert_eq!(
+            expand("<!--symbol-->main<!--symbol--><!--content-->{{ blog.title }}<!--content-->"),
+            "\"blog.title\" is used without a blog."
+        );
+        assert_eq!(
+            expand("<!--symbol-->main : blogs<!--symbol--><!--content-->{{ blog.author }}<!--content-->"),
+            "unknown slot \"blog.author\"."
+        );
+        let err = Expander::load(
+            Path::new("template.html"),
+            "<!--symbol-->main : blogs<!--symbol--><!--content-->\n<h1>{{ blog.title | date }}</h1><!--content-->",
+        )
+        .unwrap()
+        .expand(Context::new(&clusters))
+        .unwrap_err();
+        assert_eq!(err.location().unwrap().to_string(), "template.html:2:5");
+        assert!(Expander::load(
+            Path::new("template.html"),
+            "<!--symbol-->main : posts<!--symbol--><!--content--><!--content-->"
+        )
+        .is_err());
+    }
+}
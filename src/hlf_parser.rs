@@ -10,290 +10,669 @@
 //! document := symbol content document | epsilon
 //! content := content symbol content | epsilon
 //! ```txt
+//!
+//! A comment enclosing a single word is a directive, like `<!--symbol-->`.
+//! Comments with other words are ordinary comments and kept as they are.
+//!
+//! Content could include a partial file, `<!--include-->path<!--include-->`.
+//! A partial is a piece of content, which could use symbols and include other
+//! partials too, see `parse_content`.
+//!
+//! A template could extend a base layout with `<!--extends-->path<!--extends-->`
+//! outside symbols and contents, see `parse_document`.
+//!
+//! A section of content could be conditional:
+//! ```txt
+//! <!--if--> blog.preview <!--if-->...<!--else-->...<!--endif-->
+//! ```
+//! `<!--else-->` is optional and conditions could be nested.
+//!
+//! Parsed HLFs borrow from the input, nothing is copied.
 
-use std::str::Chars;
+use memchr::memmem::Finder;
+use std::fmt;
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
-pub enum Symbol {
-    N(String),
-    T(String),
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Symbol<'a> {
+    N(&'a str),
+    T(&'a str),
+    // Path of the partial included
+    I(&'a str),
+    // Condition of the section till `EndIf`, `Else` optionally in between
+    If(&'a str),
+    Else,
+    EndIf,
 }
 
-pub type HlfLhs = String;
-pub type HlfRhs = Vec<Symbol>; // Ns and Ts
+pub type HlfLhs<'a> = &'a str;
+pub type HlfRhs<'a> = Vec<Symbol<'a>>; // Ns and Ts
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
-pub struct HLF {
-    pub lhs: HlfLhs,
-    pub rhs: HlfRhs,
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq)]
+pub struct HLF<'a> {
+    pub lhs: HlfLhs<'a>,
+    pub rhs: HlfRhs<'a>,
 }
 
-impl HLF {
+impl HLF<'_> {
     pub fn new() -> Self {
         HLF {
-            lhs: String::new(),
+            lhs: "",
             rhs: Vec::new(),
         }
     }
 }
 
+// A template, `extends` is the path of the base layout if any
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document<'a> {
+    pub extends: Option<&'a str>,
+    pub hlfs: Vec<HLF<'a>>,
+    // Text outside symbols and contents, e.g. comments and blank lines, kept
+    // for printing. In the order of the input: before the extends, before
+    // each symbol and between it and its content, then the rest.
+    pub texts: Vec<&'a str>,
+    // Number of HLFs before the extends
+    pub extends_at: usize,
+}
+
 #[derive(Debug, PartialEq)]
-enum HlfType {
+enum HlfType<'a> {
     Symbol,
     Content,
+    Include,
+    Extends,
+    If,
+    Else,
+    EndIf,
+    // Directive we don't know, probably a typo
+    Unknown(&'a str),
 }
 
-/// Check if the input is prefixed by providing pattern. Return
-/// `Some(end_position)` if success.
-fn match_str<'a, 'b>(mut input: Chars<'a>, mut pattern: Chars<'b>) -> Option<Chars<'a>> {
-    loop {
-        match pattern.next() {
-            Some(x) => {
-                if input.next()? != x {
-                    return None;
-                }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    // `<!--content-->` opened but never closed
+    UnterminatedContent,
+    // `<!--symbol-->` opened but never closed
+    UnterminatedSymbol,
+    // `<!--include-->` opened but never closed
+    UnterminatedInclude,
+    // `<!--include-->` outside content
+    MisplacedInclude,
+    // `<!--content-->` in a partial
+    ContentInPartial,
+    // `<!--extends-->` opened but never closed
+    UnterminatedExtends,
+    // `<!--extends-->` inside symbol or content
+    MisplacedExtends,
+    // Template extends more than one layout
+    ExtendsTwice,
+    // `<!--if-->` opened but never closed
+    UnterminatedIf,
+    // `<!--if-->` section without `<!--endif-->`
+    MissingEndif,
+    // `<!--else-->` without `<!--if-->`, or used twice
+    UnmatchedElse,
+    // `<!--endif-->` without `<!--if-->`
+    UnmatchedEndif,
+    // `<!--if-->`, `<!--else-->` or `<!--endif-->` outside content
+    MisplacedCondition,
+    // `<!--content-->` inside `<!--symbol-->`
+    ContentInSymbol,
+    // `<!--content-->` with no symbol before it
+    ContentWithoutSymbol,
+    // Symbol defined but no content follows
+    SymbolWithoutContent(String),
+    UnknownDirective(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: usize, // in bytes
+    pub line: usize,   // 1-based
+    pub column: usize, // 1-based, in chars
+    pub kind: ParseErrorKind,
+}
+
+impl ParseError {
+    fn new(input: &str, offset: usize, kind: ParseErrorKind) -> Self {
+        let before = &input[..offset];
+        ParseError {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before.rsplit('\n').next().unwrap_or("").chars().count() + 1,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnterminatedContent => write!(f, "unterminated <!--content-->"),
+            ParseErrorKind::UnterminatedSymbol => write!(f, "unterminated <!--symbol-->"),
+            ParseErrorKind::UnterminatedInclude => write!(f, "unterminated <!--include-->"),
+            ParseErrorKind::MisplacedInclude => write!(f, "<!--include--> outside content"),
+            ParseErrorKind::ContentInPartial => write!(f, "<!--content--> in a partial"),
+            ParseErrorKind::UnterminatedExtends => write!(f, "unterminated <!--extends-->"),
+            ParseErrorKind::MisplacedExtends => {
+                write!(f, "<!--extends--> inside a symbol or content")
             }
-            None => return Some(input),
-        };
+            ParseErrorKind::ExtendsTwice => write!(f, "<!--extends--> used more than once"),
+            ParseErrorKind::UnterminatedIf => write!(f, "unterminated <!--if-->"),
+            ParseErrorKind::MissingEndif => write!(f, "<!--if--> without <!--endif-->"),
+            ParseErrorKind::UnmatchedElse => write!(f, "<!--else--> without <!--if-->"),
+            ParseErrorKind::UnmatchedEndif => write!(f, "<!--endif--> without <!--if-->"),
+            ParseErrorKind::MisplacedCondition => write!(f, "condition outside content"),
+            ParseErrorKind::ContentInSymbol => write!(f, "<!--content--> inside a symbol"),
+            ParseErrorKind::ContentWithoutSymbol => {
+                write!(f, "<!--content--> without a symbol before it")
+            }
+            ParseErrorKind::SymbolWithoutContent(x) => {
+                write!(f, "symbol \"{}\" without content", x)
+            }
+            ParseErrorKind::UnknownDirective(x) => write!(f, "unknown directive <!--{}-->", x),
+        }
     }
 }
 
-fn match_type_begin<'a>(input: Chars<'a>) -> Option<Chars<'a>> {
-    const TYPE_BEGIN: &str = "<!--";
-    match_str(input, TYPE_BEGIN.chars())
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
 }
 
-fn match_type_end<'a>(input: Chars<'a>) -> Option<Chars<'a>> {
-    const TYPE_END: &str = "-->";
-    match_str(input, TYPE_END.chars())
+// Finds directives in the input. Ordinary comments are skipped, every byte is
+// looked at a constant number of times.
+struct Scanner<'a> {
+    input: &'a str,
+    // Where to search the next `<!--`
+    position: usize,
+    // Position of the first `-->` not before `position`, None if not found
+    // yet, `input.len()` if there is no more.
+    close: Option<usize>,
+    open_finder: Finder<'static>,
+    close_finder: Finder<'static>,
 }
 
-// Return matched type like symbol and content
-fn match_type<'a>(mut input_it: Chars<'a>) -> Option<(Chars<'a>, HlfType)> {
-    let mut enclose = String::new();
-    input_it = match_type_begin(input_it.clone())?;
-    loop {
-        // Try matching end, if failed, appending enclosed word.
-        if let Some(it) = match_type_end(input_it.clone()) {
-            input_it = it;
-            return match enclose.trim() {
-                "symbol" => Some((input_it, HlfType::Symbol)),
-                "content" => Some((input_it, HlfType::Content)),
-                _ => None,
+impl<'a> Scanner<'a> {
+    fn new(input: &'a str) -> Self {
+        Scanner {
+            input,
+            position: 0,
+            close: None,
+            open_finder: Finder::new(TYPE_BEGIN),
+            close_finder: Finder::new(TYPE_END),
+        }
+    }
+
+    // Return the next directive with where it begins and ends in bytes.
+    fn next(&mut self) -> Option<(usize, usize, HlfType<'a>)> {
+        let bytes = self.input.as_bytes();
+        loop {
+            let begin = self.position + self.open_finder.find(&bytes[self.position..])?;
+            let inner = begin + TYPE_BEGIN.len();
+            // Next `<!--` could start inside this one, e.g. `<!--<!--`
+            self.position = begin + 1;
+            let close = match self.close {
+                Some(x) if x >= inner => x,
+                _ => {
+                    let x = self.close_finder.find(&bytes[inner..]).map(|x| inner + x);
+                    let x = x.unwrap_or(bytes.len());
+                    self.close = Some(x);
+                    x
+                }
             };
-        } else {
-            enclose.push(input_it.next()?);
+            if close == bytes.len() {
+                // no comment is closed from here
+                self.position = bytes.len();
+                return None;
+            }
+            if let Some(typ) = match_type(&self.input[inner..close]) {
+                return Some((begin, close + TYPE_END.len(), typ));
+            }
         }
     }
 }
 
-pub fn parse(input: &str) -> Option<Vec<HLF>> {
-    let mut input = input.chars();
+const TYPE_BEGIN: &str = "<!--";
+const TYPE_END: &str = "-->";
+
+// Return matched type like symbol and content by the word enclosed, None if
+// it's an ordinary comment
+fn match_type(enclose: &str) -> Option<HlfType<'_>> {
+    match enclose.trim() {
+        "symbol" => Some(HlfType::Symbol),
+        "content" => Some(HlfType::Content),
+        "include" => Some(HlfType::Include),
+        "extends" => Some(HlfType::Extends),
+        "if" => Some(HlfType::If),
+        "else" => Some(HlfType::Else),
+        "endif" => Some(HlfType::EndIf),
+        x if !x.is_empty() && x.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+            Some(HlfType::Unknown(x))
+        }
+        _ => None,
+    }
+}
+
+pub fn parse_document(input: &str) -> Result<Document<'_>, ParseError> {
+    parse_impl(input, false)
+}
+
+// Parse a partial, which is the inner of a `<!--content-->` pair.
+pub fn parse_content(input: &str) -> Result<HlfRhs<'_>, ParseError> {
+    Ok(parse_impl(input, true)?.hlfs.pop().unwrap().rhs)
+}
+
+// Error for the symbol part in content opened by the directive but not closed
+fn unterminated(opened: &HlfType) -> ParseErrorKind {
+    match opened {
+        HlfType::Include => ParseErrorKind::UnterminatedInclude,
+        HlfType::If => ParseErrorKind::UnterminatedIf,
+        _ => ParseErrorKind::UnterminatedSymbol,
+    }
+}
+
+// A partial starts in content and ends at the end of input.
+//
+// Jumps from directive to directive, texts between them are sliced from the
+// input rather than copied.
+fn parse_impl(input: &str, partial: bool) -> Result<Document<'_>, ParseError> {
+    let error = |offset: usize, kind: ParseErrorKind| Err(ParseError::new(input, offset, kind));
+    let mut scanner = Scanner::new(input);
 
     let mut result: Vec<HLF> = Vec::new();
-    //let mut symbol_table: HashSet<String> = ::new();
+    let mut extends: Option<&str> = None;
+    let mut texts: Vec<&str> = Vec::new();
+    let mut extends_at = 0;
 
     // Currently which part of a HLF we want to match
-    let mut get_right: bool = false;
+    let mut get_right: bool = partial;
 
     let mut insymbol: bool = false;
-    let mut incontent: bool = false;
+    let mut incontent: bool = partial;
+    // Directive opening the symbol part in content, could be an include or
+    // a condition
+    let mut opened = HlfType::Symbol;
+    // Conditions not ended in current content, with where they begin and
+    // whether `<!--else-->` is met
+    let mut conditions: Vec<(usize, bool)> = Vec::new();
+    // Symbol part outside content is actually an extends
+    let mut inextends: bool = false;
+
+    // Where the current symbol and content begins, for diagnostics
+    let mut symbol_begin = 0;
+    let mut content_begin = 0;
 
     let mut tmp_hlf: HLF = HLF::new();
-    let mut tmp_str = String::new();
+    // Where the text after last directive begins
+    let mut text_begin = 0;
 
-    loop {
+    while let Some((begin, end, typ)) = scanner.next() {
+        // Text before the directive, used or ignored depending on the state
+        let text = &input[text_begin..begin];
+        text_begin = end;
+        match typ {
+            HlfType::Unknown(x) => {
+                return error(begin, ParseErrorKind::UnknownDirective(x.to_string()))
+            }
+            // Conditions only live in content
+            HlfType::If | HlfType::Else | HlfType::EndIf if !incontent => {
+                return error(begin, ParseErrorKind::MisplacedCondition)
+            }
+            _ => (),
+        }
         match (get_right, incontent, insymbol) {
-            // Get right side and in content's symbol part
-            (true, true, true) => {
-                if let Some((it, typ)) = match_type(input.clone()) {
-                    match typ {
-                        HlfType::Symbol => {
-                            input = it;
-                            insymbol = false;
-                            // symbol should be trimmed
-                            tmp_hlf.rhs.push(Symbol::N(tmp_str.trim().to_string()));
-                            tmp_str.clear();
-                            //println!("symbol in content close");
-                        }
-                        HlfType::Content => {
-                            // no content in symbol segment
-                            return None;
+            // Get right side and in content's symbol part, which could also be
+            // an include or a condition
+            (true, true, true) => match typ {
+                HlfType::Extends => {
+                    return error(begin, ParseErrorKind::MisplacedExtends);
+                }
+                HlfType::Content => {
+                    // no content in symbol segment
+                    return error(begin, ParseErrorKind::ContentInSymbol);
+                }
+                // closed by the directive opening it
+                x if x == opened => {
+                    insymbol = false;
+                    // symbol should be trimmed
+                    let inner = text.trim();
+                    tmp_hlf.rhs.push(match x {
+                        HlfType::Symbol => Symbol::N(inner),
+                        HlfType::Include => Symbol::I(inner),
+                        HlfType::If => {
+                            conditions.push((symbol_begin, false));
+                            Symbol::If(inner)
                         }
-                    }
-                } else {
-                    tmp_str.push(input.next()?);
+                        _ => unreachable!(),
+                    });
                 }
-            }
+                _ => return error(symbol_begin, unterminated(&opened)),
+            },
             // Get right side and in content's non-symbol part
-            (true, true, false) => {
-                if let Some((it, typ)) = match_type(input.clone()) {
-                    match typ {
-                        HlfType::Symbol => {
-                            input = it;
-                            insymbol = true;
-                            tmp_hlf.rhs.push(Symbol::T(tmp_str.clone()));
-                            tmp_str.clear();
-                            //println!("get a symbol in content");
-                        }
-                        HlfType::Content => {
-                            // content ends
-                            input = it;
-                            incontent = false;
-                            get_right = false;
-                            tmp_hlf.rhs.push(Symbol::T(tmp_str.clone()));
-                            tmp_str.clear();
-                            result.push(tmp_hlf);
-                            tmp_hlf = HLF::new();
-                            //println!("content close");
-                        }
-                    }
-                } else {
-                    // append to content
-                    tmp_str.push(input.next()?);
+            (true, true, false) => match typ {
+                HlfType::Symbol | HlfType::Include | HlfType::If => {
+                    insymbol = true;
+                    opened = typ;
+                    symbol_begin = begin;
+                    tmp_hlf.rhs.push(Symbol::T(text));
                 }
-            }
-            // Get right side and not in content
-            (true, false, _) => {
-                if let Some((it, typ)) = match_type(input.clone()) {
-                    match typ {
-                        HlfType::Content => {
-                            input = it;
-                            incontent = true;
-                            //println!("get a content");
-                        }
-                        HlfType::Symbol => {
-                            return None;
-                        }
+                HlfType::Else => {
+                    match conditions.last_mut() {
+                        Some((_, has_else)) if !*has_else => *has_else = true,
+                        _ => return error(begin, ParseErrorKind::UnmatchedElse),
                     }
-                } else {
-                    // ignore the char
-                    input.next()?;
+                    tmp_hlf.rhs.push(Symbol::T(text));
+                    tmp_hlf.rhs.push(Symbol::Else);
                 }
-            }
-            // Get left side and in symbol
-            (false, _, true) => {
-                if let Some((it, typ)) = match_type(input.clone()) {
-                    match typ {
-                        HlfType::Symbol => {
-                            input = it;
-                            insymbol = false;
-                            get_right = true;
-                            tmp_hlf.lhs = tmp_str.trim().to_string();
-                            tmp_str.clear();
-                            //println!("symbol close");
-                        }
-                        HlfType::Content => {
-                            // content are not permitted in symbol
-                            return None;
-                        }
+                HlfType::EndIf => {
+                    if conditions.pop().is_none() {
+                        return error(begin, ParseErrorKind::UnmatchedEndif);
                     }
-                } else {
-                    // append symbol
-                    tmp_str.push(input.next()?);
+                    tmp_hlf.rhs.push(Symbol::T(text));
+                    tmp_hlf.rhs.push(Symbol::EndIf);
                 }
-            }
-            // Get left side and not in symbol
-            (false, _, false) => {
-                if let Some((it, typ)) = match_type(input.clone()) {
-                    match typ {
-                        HlfType::Symbol => {
-                            input = it;
-                            insymbol = true;
-                            //println!("get a symbol");
-                        }
-                        HlfType::Content => {
-                            // content are not permitted in symbol
-                            return None;
-                        }
+                HlfType::Extends => {
+                    return error(begin, ParseErrorKind::MisplacedExtends);
+                }
+                HlfType::Content if partial => {
+                    return error(begin, ParseErrorKind::ContentInPartial);
+                }
+                HlfType::Content => {
+                    if let Some((if_begin, _)) = conditions.pop() {
+                        return error(if_begin, ParseErrorKind::MissingEndif);
                     }
-                } else {
-                    // ignore
-                    if input.next().is_none() {
-                        return Some(result);
+                    // content ends
+                    incontent = false;
+                    get_right = false;
+                    tmp_hlf.rhs.push(Symbol::T(text));
+                    result.push(std::mem::take(&mut tmp_hlf));
+                }
+                HlfType::Unknown(_) => unreachable!(),
+            },
+            // Get right side and not in content, text is kept for printing
+            (true, false, _) => match typ {
+                HlfType::Content => {
+                    incontent = true;
+                    content_begin = begin;
+                    texts.push(text);
+                }
+                HlfType::Symbol => {
+                    return error(
+                        symbol_begin,
+                        ParseErrorKind::SymbolWithoutContent(tmp_hlf.lhs.to_string()),
+                    );
+                }
+                HlfType::Include => {
+                    return error(begin, ParseErrorKind::MisplacedInclude);
+                }
+                HlfType::Extends => {
+                    return error(begin, ParseErrorKind::MisplacedExtends);
+                }
+                HlfType::Unknown(_) | HlfType::If | HlfType::Else | HlfType::EndIf => {
+                    unreachable!()
+                }
+            },
+            // Get left side and in symbol
+            (false, _, true) => match typ {
+                HlfType::Symbol if !inextends => {
+                    insymbol = false;
+                    get_right = true;
+                    tmp_hlf.lhs = text.trim();
+                }
+                HlfType::Extends if inextends => {
+                    insymbol = false;
+                    inextends = false;
+                    extends = Some(text.trim());
+                }
+                HlfType::Symbol => return error(symbol_begin, ParseErrorKind::UnterminatedExtends),
+                HlfType::Extends => {
+                    return error(begin, ParseErrorKind::MisplacedExtends);
+                }
+                HlfType::Content => {
+                    // content are not permitted in symbol
+                    return error(begin, ParseErrorKind::ContentInSymbol);
+                }
+                HlfType::Include => {
+                    return error(begin, ParseErrorKind::MisplacedInclude);
+                }
+                HlfType::Unknown(_) | HlfType::If | HlfType::Else | HlfType::EndIf => {
+                    unreachable!()
+                }
+            },
+            // Get left side and not in symbol, text is kept for printing
+            (false, _, false) => match typ {
+                HlfType::Symbol => {
+                    insymbol = true;
+                    symbol_begin = begin;
+                    texts.push(text);
+                }
+                HlfType::Extends => {
+                    if extends.is_some() {
+                        return error(begin, ParseErrorKind::ExtendsTwice);
                     }
+                    insymbol = true;
+                    inextends = true;
+                    symbol_begin = begin;
+                    texts.push(text);
+                    extends_at = result.len();
+                }
+                HlfType::Content => {
+                    // content should follow a symbol
+                    return error(begin, ParseErrorKind::ContentWithoutSymbol);
+                }
+                HlfType::Include => {
+                    return error(begin, ParseErrorKind::MisplacedInclude);
                 }
+                HlfType::Unknown(_) | HlfType::If | HlfType::Else | HlfType::EndIf => {
+                    unreachable!()
+                }
+            },
+        }
+    }
+
+    // No more directives
+    match (get_right, incontent, insymbol) {
+        (true, true, true) => error(symbol_begin, unterminated(&opened)),
+        (true, true, false) if partial => {
+            if let Some((if_begin, _)) = conditions.pop() {
+                return error(if_begin, ParseErrorKind::MissingEndif);
             }
+            tmp_hlf.rhs.push(Symbol::T(&input[text_begin..]));
+            result.push(tmp_hlf);
+            Ok(Document {
+                extends,
+                hlfs: result,
+                texts,
+                extends_at,
+            })
+        }
+        (true, true, false) => error(content_begin, ParseErrorKind::UnterminatedContent),
+        (true, false, _) => error(
+            symbol_begin,
+            ParseErrorKind::SymbolWithoutContent(tmp_hlf.lhs.to_string()),
+        ),
+        (false, _, true) if inextends => error(symbol_begin, ParseErrorKind::UnterminatedExtends),
+        (false, _, true) => error(symbol_begin, ParseErrorKind::UnterminatedSymbol),
+        (false, _, false) => {
+            texts.push(&input[text_begin..]);
+            Ok(Document {
+                extends,
+                hlfs: result,
+                texts,
+                extends_at,
+            })
         }
     }
 }
 
+// Serialize the right side back into content, markers are normalized.
+fn print_rhs(rhs: &[Symbol], result: &mut String) {
+    for symbol in rhs {
+        match symbol {
+            Symbol::T(x) => result.push_str(x),
+            Symbol::N(x) => {
+                result.push_str("<!--symbol--> ");
+                result.push_str(x);
+                result.push_str(" <!--symbol-->");
+            }
+            Symbol::I(x) => {
+                result.push_str("<!--include--> ");
+                result.push_str(x);
+                result.push_str(" <!--include-->");
+            }
+            Symbol::If(x) => {
+                result.push_str("<!--if--> ");
+                result.push_str(x);
+                result.push_str(" <!--if-->");
+            }
+            Symbol::Else => result.push_str("<!--else-->"),
+            Symbol::EndIf => result.push_str("<!--endif-->"),
+        }
+    }
+}
+
+// Serialize a document back into template text. Markers are written as
+// `<!--symbol--> name <!--symbol-->` whatever they were, contents and text
+// outside them, e.g. comments between definitions, are kept.
+pub fn print_document(document: &Document) -> String {
+    let mut texts = document.texts.iter();
+    let mut text = || texts.next().copied().unwrap_or_default();
+    let mut result = String::new();
+    for i in 0..=document.hlfs.len() {
+        if let Some(x) = document.extends.filter(|_| i == document.extends_at) {
+            result.push_str(text());
+            result.push_str("<!--extends--> ");
+            result.push_str(x);
+            result.push_str(" <!--extends-->");
+        }
+        let Some(hlf) = document.hlfs.get(i) else {
+            break;
+        };
+        result.push_str(text());
+        result.push_str("<!--symbol--> ");
+        result.push_str(hlf.lhs);
+        result.push_str(" <!--symbol-->");
+        result.push_str(text());
+        result.push_str("<!--content-->");
+        print_rhs(&hlf.rhs, &mut result);
+        result.push_str("<!--content-->");
+    }
+    result.push_str(text());
+    result
+}
+
+// Serialize a partial back into text, see `parse_content`.
+pub fn print_content(rhs: &[Symbol]) -> String {
+    let mut result = String::new();
+    print_rhs(rhs, &mut result);
+    result
+}
+
+// Parse a template, or a partial if it isn't a document or defines nothing,
+// then pass it to the function of its kind. The error is of the document if
+// neither parses.
+fn inspect(
+    input: &str,
+    document: impl Fn(&Document) -> String,
+    content: impl Fn(&[Symbol]) -> String,
+) -> Result<String, ParseError> {
+    match parse_document(input) {
+        Ok(x) if !x.hlfs.is_empty() || x.extends.is_some() => Ok(document(&x)),
+        Ok(_) => Ok(content(&parse_content(input)?)),
+        Err(err) => parse_content(input).map(|x| content(&x)).map_err(|_| err),
+    }
+}
+
+pub fn format_template(input: &str) -> Result<String, ParseError> {
+    inspect(input, print_document, print_content)
+}
+
+pub fn dump_template(input: &str) -> Result<String, ParseError> {
+    inspect(input, dump, dump_content)
+}
+
+fn dump_rhs(rhs: &[Symbol]) -> String {
+    let rhs: Vec<String> = rhs
+        .iter()
+        .map(|x| match x {
+            Symbol::T(_) => "T".to_string(),
+            Symbol::N(x) => x.to_string(),
+            Symbol::I(x) => format!("include({})", x),
+            Symbol::If(x) => format!("if({})", x),
+            Symbol::Else => "else".to_string(),
+            Symbol::EndIf => "endif".to_string(),
+        })
+        .collect();
+    rhs.join(" ")
+}
+
+// Show symbols as a grammar, one line per symbol, e.g. `main := T tags T`.
+pub fn dump(document: &Document) -> String {
+    let mut result = String::new();
+    if let Some(x) = &document.extends {
+        result.push_str(&format!("extends {}\n", x));
+    }
+    for hlf in document.hlfs.iter() {
+        result.push_str(&format!("{} := {}\n", hlf.lhs, dump_rhs(&hlf.rhs)));
+    }
+    result
+}
+
+// Show a partial as a grammar, e.g. `content := T include(a.html) T`.
+pub fn dump_content(rhs: &[Symbol]) -> String {
+    format!("content := {}\n", dump_rhs(rhs))
+}
+
 #[cfg(test)]
 mod hlf_parser_tests {
     use super::*;
 
-    #[test]
-    fn test_match_str() {
-        macro_rules! can_match {
-            ($input: expr, $pattern: expr) => {
-                assert!(match_str($input.chars(), $pattern.chars()).is_some());
-            };
-        }
-        macro_rules! cannot_match {
-            ($input: expr, $pattern: expr) => {
-                assert!(match_str($input.chars(), $pattern.chars()).is_none());
-            };
-        }
-        can_match!("a", "a");
-        can_match!("aaa", "aaa");
-        can_match!("Hello😁world Yeah!", "Hello😁world");
-        can_match!("😁world Yeah!", "😁world");
-        cannot_match!("a😁world Yeah!", "😁world");
-        cannot_match!("😁worl😁world Yeah!", "😁world");
-        cannot_match!("world", "worldemm");
-        cannot_match!("world", "aworld");
+    // Parse and print, so the template is formatted.
+    fn normalize(input: &str) -> Result<String, ParseError> {
+        Ok(print_document(&parse_document(input)?))
     }
 
-    #[test]
-    fn test_match_type_begin() {
-        macro_rules! can_match_begin {
-            ($input: expr) => {
-                assert!(match_type_begin($input.chars()).is_some());
-            };
-        }
-        macro_rules! cannot_match_begin {
-            ($input: expr) => {
-                assert!(match_type_begin($input.chars()).is_none());
-            };
-        }
-        can_match_begin!("<!--");
-        can_match_begin!("<!----");
-        cannot_match_begin!("<!-");
-        cannot_match_begin!("<!-<!--");
-        cannot_match_begin!("😒<!--");
+    fn parse(input: &str) -> Result<Vec<HLF<'_>>, ParseError> {
+        Ok(parse_document(input)?.hlfs)
     }
 
     #[test]
-    fn test_match_type_end() {
-        macro_rules! can_match_end {
-            ($input: expr) => {
-                assert!(match_type_end($input.chars()).is_some());
-            };
-        }
-        macro_rules! cannot_match_end {
-            ($input: expr) => {
-                assert!(match_type_end($input.chars()).is_none());
-            };
-        }
-        can_match_end!("-->");
-        can_match_end!("-->--");
-        cannot_match_end!("--->");
-        cannot_match_end!("<!-->");
-        cannot_match_end!("<!----");
-        cannot_match_end!("<!--");
+    fn test_match_type() {
+        assert_eq!(Some(HlfType::Symbol), match_type("symbol"));
+        assert_eq!(Some(HlfType::Content), match_type(" content "));
+        assert_eq!(Some(HlfType::Unknown("hahaha")), match_type(" hahaha "));
+        assert_eq!(None, match_type("ha ha ha"));
+        assert_eq!(None, match_type(""));
     }
 
     #[test]
-    fn test_match_type() {
-        let (_, typ) = match_type("<!--symbol-->".chars()).unwrap();
-        assert_eq!(HlfType::Symbol, typ);
-        let (_, typ) = match_type("<!--content-->".chars()).unwrap();
-        assert_eq!(HlfType::Content, typ);
-        if let None = match_type("<!--hahaha-->".chars()) {
-            assert!(true);
-        } else {
-            assert!(false);
+    fn test_scanner() {
+        macro_rules! directives {
+            ($input: expr) => {{
+                let mut scanner = Scanner::new($input);
+                let mut result = Vec::new();
+                while let Some(x) = scanner.next() {
+                    result.push(x);
+                }
+                result
+            }};
         }
+        assert_eq!(
+            directives!("a<!--symbol-->😁<!-- content -->"),
+            vec![(1, 14, HlfType::Symbol), (18, 34, HlfType::Content)]
+        );
+        // ordinary comments are skipped, but directives inside them are not
+        assert_eq!(
+            directives!("<!-- a comment --><!--<!--if-->--><!---->"),
+            vec![(22, 31, HlfType::If)]
+        );
+        assert_eq!(directives!("<!--symbol"), vec![]);
+        assert_eq!(directives!("<!--->"), vec![]);
+        assert_eq!(directives!("-->"), vec![]);
+        assert_eq!(directives!(""), vec![]);
     }
 
     #[test]
@@ -302,8 +681,8 @@ mod hlf_parser_tests {
         let result = parse(input).unwrap();
         assert_eq!(result.len(), 1);
         let result: &HLF = &result[0];
-        assert_eq!(result.lhs, String::from("a"));
-        assert_eq!(result.rhs, vec![Symbol::T(String::from("b"))]);
+        assert_eq!(result.lhs, "a");
+        assert_eq!(result.rhs, vec![Symbol::T("b")]);
     }
 
     #[test]
@@ -312,11 +691,8 @@ mod hlf_parser_tests {
         let result = parse(input).unwrap();
         assert_eq!(result.len(), 1);
         let result: &HLF = &result[0];
-        assert_eq!(result.lhs, String::from("this is the symbol"));
-        assert_eq!(
-            result.rhs,
-            vec![Symbol::T(String::from("this is the content"))]
-        );
+        assert_eq!(result.lhs, "this is the symbol");
+        assert_eq!(result.rhs, vec![Symbol::T("this is the content")]);
     }
 
     #[test]
@@ -326,11 +702,8 @@ mod hlf_parser_tests {
         let result = parse(input).unwrap();
         assert_eq!(result.len(), 1);
         let result: &HLF = &result[0];
-        assert_eq!(result.lhs, String::from("this is the symbol"));
-        assert_eq!(
-            result.rhs,
-            vec![Symbol::T(String::from("this is the content"))]
-        );
+        assert_eq!(result.lhs, "this is the symbol");
+        assert_eq!(result.rhs, vec![Symbol::T("this is the content")]);
     }
 
     #[test]
@@ -339,11 +712,8 @@ mod hlf_parser_tests {
         let result = parse(input).unwrap();
         assert_eq!(result.len(), 1);
         let result: &HLF = &result[0];
-        assert_eq!(result.lhs, String::from("symbol_name_with_padding"));
-        assert_eq!(
-            result.rhs,
-            vec![Symbol::T(String::from(" content with padding "))]
-        );
+        assert_eq!(result.lhs, "symbol_name_with_padding");
+        assert_eq!(result.rhs, vec![Symbol::T(" content with padding ")]);
     }
 
     #[test]
@@ -351,7 +721,7 @@ mod hlf_parser_tests {
         let input = "<!--symbol-->     <!--this is a comment--> <!--symbol--><!--content--> content with padding <!--content-->";
         let symbol = "<!--this is a comment-->";
         let result = parse(input).unwrap();
-        assert_eq!(result[0].lhs, symbol.to_string());
+        assert_eq!(result[0].lhs, symbol);
     }
 
     #[test]
@@ -389,10 +759,10 @@ mod hlf_parser_tests {
         assert_eq!(result[0].rhs.len(), 1);
         assert_eq!(result[1].rhs.len(), 1);
 
-        assert_eq!(result[0].lhs, symbol.to_string());
-        assert_eq!(result[1].lhs, symbol.to_string());
-        assert_eq!(result[0].rhs[0], Symbol::T(content.to_string()));
-        assert_eq!(result[1].rhs[0], Symbol::T(content.to_string()));
+        assert_eq!(result[0].lhs, symbol);
+        assert_eq!(result[1].lhs, symbol);
+        assert_eq!(result[0].rhs[0], Symbol::T(content));
+        assert_eq!(result[1].rhs[0], Symbol::T(content));
     }
 
     #[test]
@@ -419,4 +789,345 @@ mod hlf_parser_tests {
         let input = "<!--symbol--> the symbol <!--symbol-->";
         parse(input).unwrap();
     }
+
+    #[test]
+    fn test_parse_errors() {
+        macro_rules! parse_error {
+            ($input: expr, $line: expr, $column: expr, $kind: expr) => {
+                let err = parse($input).unwrap_err();
+                assert_eq!((err.line, err.column, err.kind), ($line, $column, $kind));
+            };
+        }
+        parse_error!(
+            "<!--symbol-->a<!--symbol-->\n  <!--content-->b",
+            2,
+            3,
+            ParseErrorKind::UnterminatedContent
+        );
+        parse_error!(
+            "<!--symbol-->a<!--symbol--><!--content--><!--symbol-->b",
+            1,
+            42,
+            ParseErrorKind::UnterminatedSymbol
+        );
+        parse_error!(
+            "<!--symbol-->a<!--content-->",
+            1,
+            15,
+            ParseErrorKind::ContentInSymbol
+        );
+        parse_error!(
+            "<!--symbol-->a<!--symbol--><!--content--><!--symbol-->\n<!--content-->",
+            2,
+            1,
+            ParseErrorKind::ContentInSymbol
+        );
+        parse_error!(
+            "\n\n   <!--content-->",
+            3,
+            4,
+            ParseErrorKind::ContentWithoutSymbol
+        );
+        parse_error!(
+            "😁<!--symbol-->a<!--symbol-->\n<!--symbol-->b<!--symbol-->",
+            1,
+            2,
+            ParseErrorKind::SymbolWithoutContent("a".to_string())
+        );
+        parse_error!(
+            "<!--symbol-->a<!--symbol-->\n<!--content-->\n<!--contnet-->",
+            3,
+            1,
+            ParseErrorKind::UnknownDirective("contnet".to_string())
+        );
+        let err = parse("<!--symbol-->a<!--symbol-->\n<!--synbol-->").unwrap_err();
+        assert_eq!(28, err.offset);
+        assert_eq!("2:1: unknown directive <!--synbol-->", err.to_string());
+    }
+
+    #[test]
+    fn test_parse_include() {
+        let result = parse(
+            "<!--symbol-->main<!--symbol--><!--content-->a<!-- include --> b.html <!--include--><!--symbol-->c<!--symbol--><!--content-->",
+        )
+        .unwrap();
+        assert_eq!(
+            result[0].rhs,
+            vec![
+                Symbol::T("a"),
+                Symbol::I("b.html"),
+                Symbol::T(""),
+                Symbol::N("c"),
+                Symbol::T(""),
+            ]
+        );
+        assert_eq!(
+            parse("<!--include-->a.html<!--include-->")
+                .unwrap_err()
+                .kind,
+            ParseErrorKind::MisplacedInclude
+        );
+        assert_eq!(
+            parse("<!--symbol-->main<!--symbol--><!--content--><!--include-->a.html<!--symbol-->")
+                .unwrap_err()
+                .kind,
+            ParseErrorKind::UnterminatedInclude
+        );
+    }
+
+    #[test]
+    fn test_parse_extends() {
+        let result = parse_document(
+            "<!-- a comment -->\n<!--extends--> layout.html <!--extends-->\n<!--symbol-->body<!--symbol--><!--content-->b<!--content-->",
+        )
+        .unwrap();
+        assert_eq!(result.extends, Some("layout.html"));
+        assert_eq!(result.hlfs.len(), 1);
+        assert_eq!(parse_document("").unwrap().extends, None);
+        macro_rules! extends_error {
+            ($input: expr, $kind: expr) => {
+                assert_eq!(parse_document($input).unwrap_err().kind, $kind);
+            };
+        }
+        extends_error!(
+            "<!--extends-->a.html<!--extends--><!--extends-->b.html<!--extends-->",
+            ParseErrorKind::ExtendsTwice
+        );
+        extends_error!(
+            "<!--extends-->a.html<!--symbol-->",
+            ParseErrorKind::UnterminatedExtends
+        );
+        extends_error!("<!--extends-->a.html", ParseErrorKind::UnterminatedExtends);
+        extends_error!(
+            "<!--symbol-->main<!--symbol--><!--content--><!--extends-->a.html<!--extends--><!--content-->",
+            ParseErrorKind::MisplacedExtends
+        );
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        let result = parse(
+            "<!--symbol-->main<!--symbol--><!--content-->a<!--if--> blog.tags <!--if-->b<!--else-->c<!--endif--><!--content-->",
+        )
+        .unwrap();
+        assert_eq!(
+            result[0].rhs,
+            vec![
+                Symbol::T("a"),
+                Symbol::If("blog.tags"),
+                Symbol::T("b"),
+                Symbol::Else,
+                Symbol::T("c"),
+                Symbol::EndIf,
+                Symbol::T(""),
+            ]
+        );
+        macro_rules! condition_error {
+            ($input: expr, $column: expr, $kind: expr) => {
+                let err = parse($input).unwrap_err();
+                assert_eq!((err.column, err.kind), ($column, $kind));
+            };
+        }
+        condition_error!(
+            "<!--symbol-->a<!--symbol--><!--content-->x<!--if-->b<!--if-->y<!--content-->",
+            43,
+            ParseErrorKind::MissingEndif
+        );
+        condition_error!(
+            "<!--symbol-->a<!--symbol--><!--content--><!--if-->b<!--symbol-->",
+            42,
+            ParseErrorKind::UnterminatedIf
+        );
+        condition_error!(
+            "<!--symbol-->a<!--symbol--><!--content--><!--else--><!--content-->",
+            42,
+            ParseErrorKind::UnmatchedElse
+        );
+        condition_error!(
+            "<!--symbol-->a<!--symbol--><!--content--><!--if-->b<!--if--><!--else--><!--else--><!--endif--><!--content-->",
+            72,
+            ParseErrorKind::UnmatchedElse
+        );
+        condition_error!(
+            "<!--symbol-->a<!--symbol--><!--content--><!--endif--><!--content-->",
+            42,
+            ParseErrorKind::UnmatchedEndif
+        );
+        condition_error!(
+            "<!--if-->b<!--if--><!--symbol-->a<!--symbol--><!--content--><!--content-->",
+            1,
+            ParseErrorKind::MisplacedCondition
+        );
+        let err = parse_content("<!--if-->b<!--if-->").unwrap_err();
+        assert_eq!(ParseErrorKind::MissingEndif, err.kind);
+    }
+
+    #[test]
+    fn test_print() {
+        let input = "<!-- symbol -->  main<!--symbol-->\n\n <!--content--> a <!-- symbol -->b<!--symbol--><!--if-->!blog.tags<!--if-->c<!--else--><!--include-->x.html<!--include--><!--endif--><!--content-->
+            <!--symbol-->b<!--symbol--><!--content--><!--content-->";
+        let printed = "<!--symbol--> main <!--symbol-->\n\n <!--content--> a <!--symbol--> b <!--symbol--><!--if--> !blog.tags <!--if-->c<!--else--><!--include--> x.html <!--include--><!--endif--><!--content-->
+            <!--symbol--> b <!--symbol--><!--content--><!--content-->";
+        assert_eq!(normalize(input).unwrap(), printed);
+        assert_eq!(normalize(printed).unwrap(), printed);
+        assert_eq!(
+            normalize("<!--extends-->base.html<!--extends-->").unwrap(),
+            "<!--extends--> base.html <!--extends-->"
+        );
+        // Comments and blank lines between definitions are kept
+        let printed = "<!-- a layout -->
+
+<!--symbol--> a <!--symbol-->
+<!--content-->x<!--content-->
+
+<!--extends--> base.html <!--extends-->
+<!-- b is empty -->
+<!--symbol--> b <!--symbol--> <!-- really empty -->
+<!--content--><!--content-->
+
+";
+        assert_eq!(normalize(printed).unwrap(), printed);
+        let document = parse_document(printed).unwrap();
+        assert_eq!(1, document.extends_at);
+        assert_eq!(6, document.texts.len());
+    }
+
+    #[test]
+    fn test_format_template() {
+        // Partials are content
+        let partial =
+            "<div><!--symbol-->tag<!--symbol--></div>\n<!--if-->blog.tags<!--if-->,<!--endif-->";
+        assert_eq!(
+            format_template(partial).unwrap(),
+            "<div><!--symbol--> tag <!--symbol--></div>\n<!--if--> blog.tags <!--if-->,<!--endif-->"
+        );
+        assert_eq!(
+            dump_template(partial).unwrap(),
+            "content := T tag T if(blog.tags) T endif T\n"
+        );
+        assert_eq!(
+            format_template("<!-- only text -->\n").unwrap(),
+            "<!-- only text -->\n"
+        );
+        // Error of the document
+        let err = format_template("<!--symbol-->a<!--symbol--><!--content-->").unwrap_err();
+        assert_eq!(ParseErrorKind::UnterminatedContent, err.kind);
+    }
+
+    #[test]
+    fn test_dump() {
+        let document = parse_document(
+            "<!--extends-->base.html<!--extends-->
+            <!--symbol-->main<!--symbol--><!--content--><!--symbol-->tags<!--symbol--><!--content-->
+            <!--symbol-->tags<!--symbol--><!--content--><!--if-->blog.tags<!--if--><!--symbol-->tag<!--symbol--><!--endif--><!--content-->",
+        )
+        .unwrap();
+        assert_eq!(
+            dump(&document),
+            "extends base.html
+main := T tags T
+tags := T if(blog.tags) T tag T endif T
+"
+        );
+    }
+
+    // parse -> print -> parse is identity, and formatting keeps every file
+    // under assets as it is
+    #[test]
+    fn test_round_trip_assets() {
+        fn walk(dir: &std::path::Path, paths: &mut Vec<std::path::PathBuf>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    walk(&path, paths);
+                } else {
+                    paths.push(path);
+                }
+            }
+        }
+        let mut paths = Vec::new();
+        walk(
+            &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets"),
+            &mut paths,
+        );
+        let mut count = 0;
+        for path in paths {
+            let Ok(raw) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            if let Ok(document) = parse_document(&raw) {
+                let printed = print_document(&document);
+                assert_eq!(parse_document(&printed).unwrap(), document, "{:?}", path);
+            }
+            assert_eq!(format_template(&raw).unwrap(), raw, "{:?}", path);
+            count += 1;
+        }
+        assert!(count >= 6);
+    }
+
+    // Generate a template of `n` symbols, each with ordinary comments, slots
+    // and conditions in it.
+    fn large_template(n: usize) -> String {
+        let mut result = String::new();
+        for i in 0..n {
+            result.push_str(&format!(
+                "<!-- symbol {i} --><!--symbol--> s{i} : blogs <!--symbol-->
+<!--content--><div class=\"blog\"><!-- an ordinary comment -->
+    <a href=\"{{{{ blog.path }}}}\">{{{{ blog.title }}}}</a> 😁
+    <!--if--> blog.preview <!--if--><p>{{{{ blog.preview }}}}</p><!--else--><p></p><!--endif-->
+    <!--symbol--> s{} <!--symbol-->
+</div><!--content-->
+",
+                i + 1
+            ));
+        }
+        result
+    }
+
+    // Parsing should be linear, run with
+    // `cargo test --release -- --ignored bench_parse --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_parse_large_template() {
+        use std::time::Instant;
+        const ROUNDS: u32 = 10;
+        let mut per_byte = Vec::new();
+        for n in [1000, 10000, 100000] {
+            let input = large_template(n);
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                assert_eq!(parse(&input).unwrap().len(), n);
+            }
+            let elapsed = start.elapsed() / ROUNDS;
+            println!(
+                "{} symbols, {} bytes: {:?}, {:.1} MB/s",
+                n,
+                input.len(),
+                elapsed,
+                input.len() as f64 / elapsed.as_secs_f64() / 1e6
+            );
+            per_byte.push(elapsed.as_secs_f64() / input.len() as f64);
+        }
+        // generous, just catching something quadratic
+        assert!(per_byte[2] < per_byte[0] * 10.0);
+    }
+
+    #[test]
+    fn test_parse_content() {
+        assert_eq!(
+            parse_content("a<!--symbol-->b<!--symbol-->\n<!--include-->c.html<!--include-->")
+                .unwrap(),
+            vec![
+                Symbol::T("a"),
+                Symbol::N("b"),
+                Symbol::T("\n"),
+                Symbol::I("c.html"),
+                Symbol::T(""),
+            ]
+        );
+        assert_eq!(parse_content("").unwrap(), vec![Symbol::T("")]);
+        let err = parse_content("a\n<!--content-->").unwrap_err();
+        assert_eq!((2, 1), (err.line, err.column));
+        assert_eq!(ParseErrorKind::ContentInPartial, err.kind);
+    }
 }
@@ -1,7 +1,15 @@
 mod blog;
 mod blog_clusters;
+mod cache;
+mod cli;
+mod config;
+mod error;
+mod expander;
 mod hlf_parser;
+mod metadata;
+mod serve;
 mod shared;
+mod slot;
 /**
  * Auto matically convert raw markdown blogs to my serveral blog web pages
  */
@@ -9,105 +17,663 @@ mod tag;
 mod template_blog;
 mod template_cluster;
 mod template_homepage;
+mod template_tag;
+mod watch;
 
 use blog_clusters::BlogClusters;
-use shared::HTMLTemplate;
+use cache::{Cache, Hasher};
+use cli::{Command, Subcommand, FAILURE, SUCCESS, USAGE};
+use config::Config;
+use error::{Error, Location, Reporter};
+use expander::{Context, Expander};
+use serve::Server;
+use shared::{path_title, HTMLTemplate};
 use template_blog::BlogTemplate;
 use template_cluster::ClusterTemplate;
 use template_homepage::HomepageTemplate;
+use template_tag::TagTemplate;
+use watch::Watcher;
 
 // for directory iteration, template read, result write
-use dotenv;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-fn get_blog_mds(blog_path: &str) -> Vec<(String, String)> {
-    let blog_subdirs =
-        fs::read_dir(blog_path).expect(&format!("read blog directory: {} failed.", blog_path));
-
-    let blog_markdown_names: Vec<String> = blog_subdirs
-        .map(|x| x.unwrap().file_name().into_string().unwrap())
-        .collect();
+// Markdown files of blogs besides the ones `parsed` already
+fn get_blog_mds(
+    blog_path: &Path,
+    parsed: &HashSet<&PathBuf>,
+    reporter: &mut Reporter,
+) -> Vec<(PathBuf, String)> {
+    let blog_subdirs = match fs::read_dir(blog_path) {
+        Ok(x) => x,
+        Err(err) => {
+            reporter.report(Error::Io(Location::file(blog_path), err));
+            return Vec::new();
+        }
+    };
 
-    let blog_markdown_paths: Vec<String> = blog_markdown_names
-        .iter()
-        .map(|x| blog_path.to_string() + x)
+    let blog_markdown_paths: Vec<PathBuf> = blog_subdirs
+        .filter_map(|x| reporter.check(x.map_err(|err| Error::Io(Location::file(blog_path), err))))
+        .map(|x| x.path())
+        .filter(|x| !parsed.contains(x))
         .collect();
 
-    // Return filenames zipped with contents
-    let blogs: Vec<(String, String)> = blog_markdown_names
-        .iter()
-        .cloned()
-        .map(|mut x| {
-            // length -3 to remove ".md"
-            x.truncate(x.len() - 3);
-            x
+    // Return paths zipped with contents
+    blog_markdown_paths
+        .into_iter()
+        .filter_map(|x| {
+            let content = fs::read_to_string(&x).map_err(|err| Error::Io(Location::file(&x), err));
+            reporter.check(content).map(|content| (x, content))
         })
-        .zip(
-            blog_markdown_paths
-                .iter()
-                .map(|x| fs::read_to_string(x).unwrap()),
-        )
-        .collect();
-    blogs
+        .collect()
 }
 
-fn main() {
-    dotenv::dotenv().ok();
-    let tags_path =
-        env::var("TAGS_PATH").expect("Please specify tags path in environment variable.");
-    let homepage_template_path = env::var("TEMPLATE_HOMEPAGE_PATH")
-        .expect("Please specify homepage template path in environment variable.");
-    let blog_template_path = env::var("TEMPLATE_BLOG_PATH")
-        .expect("Please specify blog template path in environment variable.");
-    let cluster_template_path = env::var("TEMPLATE_CLUSTER_PATH")
-        .expect("Please specify cluster template path in environment variable.");
-    let output_path =
-        env::var("OUTPUT_PATH").expect("Please specify output path in environment variable.");
-    let blog_path =
-        env::var("BLOG_PATH").expect("Please specify blog path in environment variable.");
-
-    let homepage_template_raw =
-        fs::read_to_string(&homepage_template_path).expect("homepage template not found!");
-    let blog_template_raw =
-        fs::read_to_string(&blog_template_path).expect("blog template not found!");
-    let cluster_template_raw =
-        fs::read_to_string(&cluster_template_path).expect("cluster template not found!");
-
-    let homepage_template: HomepageTemplate = HTMLTemplate::load(&homepage_template_raw).unwrap();
-    let blog_template: BlogTemplate = HTMLTemplate::load(&blog_template_raw).unwrap();
-    let cluster_template: ClusterTemplate = HTMLTemplate::load(&cluster_template_raw).unwrap();
-
-    let tags: String = fs::read_to_string(&tags_path).expect("failed to read tags.");
-    let blog_mds: Vec<(String, String)> = get_blog_mds(&blog_path);
-
-    let mut blog_clusters = BlogClusters::new();
-    blog_clusters.add_tags(&tags);
-    blog_clusters.add_blogs(&blog_mds);
-
-    let blog_html_result: Vec<(String, String)> = blog_template.fill(&blog_clusters);
-    let cluster_html_result: Vec<(String, String)> = cluster_template.fill(&blog_clusters);
-    let homepage_html_result: Vec<(String, String)> = homepage_template.fill(&blog_clusters);
-    assert_eq!(cluster_html_result.len(), 1);
-    assert_eq!(homepage_html_result.len(), 1);
-
-    match fs::create_dir_all(&output_path) {
-        Ok(_) => println!("Create direcotry \"{}\" if not exist.", &output_path),
+// Read and load the template, problems are reported.
+fn load_template<T: HTMLTemplate>(template_path: &Path, reporter: &mut Reporter) -> Option<T> {
+    let template_raw = fs::read_to_string(template_path)
+        .map_err(|err| Error::Io(Location::file(template_path), err));
+    let template_raw = reporter.check(template_raw)?;
+    reporter.check(T::load(template_path, &template_raw))
+}
+
+// Format the templates in place, or print their grammar if `dump`. Partials
+// are formatted as content.
+fn inspect_templates(template_paths: &[String], dump: bool) -> i32 {
+    let mut reporter = Reporter::new();
+    for template_path in template_paths {
+        let template_raw = fs::read_to_string(template_path)
+            .map_err(|err| Error::Io(Location::file(template_path), err));
+        let Some(template_raw) = reporter.check(template_raw) else {
+            continue;
+        };
+        let inspected = match dump {
+            true => hlf_parser::dump_template(&template_raw),
+            false => hlf_parser::format_template(&template_raw),
+        }
+        .map_err(|err| {
+            Error::Template(
+                Location::new(template_path, err.line, err.column),
+                err.kind.to_string(),
+            )
+        });
+        let Some(inspected) = reporter.check(inspected) else {
+            continue;
+        };
+        if dump {
+            println!("# {}\n{}", template_path, inspected);
+            continue;
+        }
+        if inspected != template_raw {
+            match fs::write(template_path, inspected) {
+                Ok(_) => println!("Format \"{}\" ok.", template_path),
+                Err(err) => reporter.report(Error::Io(Location::file(template_path), err)),
+            }
+        }
+    }
+    exit_code(&mut reporter)
+}
+
+fn exit_code(reporter: &mut Reporter) -> i32 {
+    if reporter.print() {
+        FAILURE
+    } else {
+        SUCCESS
+    }
+}
+
+// Kept between rebuilds of `watch`
+#[derive(Default)]
+struct Session {
+    // Blogs and tags of the last build
+    clusters: Option<BlogClusters>,
+    // Files changed since
+    changed: Vec<PathBuf>,
+}
+
+impl Session {
+    // Blogs and tags, problems are reported. Blogs of the last build are
+    // kept, only markdown files changed since and the ones not parsed into a
+    // blog, i.e. drafts and broken ones, are parsed again. Everything is
+    // parsed again if the tags changed.
+    fn load_clusters(&mut self, config: &Config, reporter: &mut Reporter) -> &BlogClusters {
+        let last = match self.changed.contains(&config.tags_path) {
+            true => None,
+            false => self.clusters.take(),
+        };
+        let changed = std::mem::take(&mut self.changed);
+        let mut blog_clusters = match last {
+            Some(mut x) => {
+                x.remove_blogs(&changed);
+                x
+            }
+            None => {
+                let tags = fs::read_to_string(&config.tags_path)
+                    .map_err(|err| Error::Io(Location::file(&config.tags_path), err));
+                let tags: String = reporter.check(tags).unwrap_or_default();
+                let mut blog_clusters = BlogClusters::new();
+                let errors = reporter.errors().len();
+                blog_clusters.add_tags(&config.tags_path, &tags, reporter);
+                // So its problems are reported again
+                if reporter.errors().len() > errors {
+                    self.changed.push(config.tags_path.clone());
+                }
+                blog_clusters
+            }
+        };
+        let parsed: HashSet<&PathBuf> = blog_clusters
+            .get_blogs()
+            .iter()
+            .map(|x| &x.source)
+            .collect();
+        let blog_mds = get_blog_mds(&config.blog_dir, &parsed, reporter);
+        blog_clusters.add_blogs(&blog_mds, reporter);
+        self.clusters.insert(blog_clusters)
+    }
+}
+
+// File names, inputs hashes and contents of pages, problems are reported.
+// Pages whose inputs didn't change since they were written aren't generated
+// again, their contents are None.
+fn generate(
+    config: &Config,
+    session: &mut Session,
+    cache: &mut Cache,
+    reporter: &mut Reporter,
+) -> Vec<(String, u64, Option<String>)> {
+    let homepage_template: Option<HomepageTemplate> =
+        load_template(&config.homepage_template, reporter);
+    let blog_template: Option<BlogTemplate> = load_template(&config.blog_template, reporter);
+    let cluster_template: Option<ClusterTemplate> =
+        load_template(&config.cluster_template, reporter);
+    let tag_template: Option<TagTemplate> = load_template(&config.tag_template, reporter);
+
+    // Or every code block would fail the same way
+    let highlight = &config.highlight;
+    let themes = [
+        ("highlight.theme", Some(&highlight.theme)),
+        ("highlight.dark_theme", highlight.dark_theme.as_ref()),
+    ];
+    let mut valid = true;
+    for (key, theme) in themes {
+        if let Some(Err(reason)) = theme.map(|x| template_blog::find_theme(x)) {
+            let location = config.locations.get(key).cloned();
+            reporter.report(Error::Config(location, reason));
+            valid = false;
+        }
+    }
+    if highlight.dark_theme.is_some() && !highlight.classes {
+        let reason =
+            "inline styles can't follow the color scheme, set \"highlight.style\" to \"classes\".";
+        let location = config.locations.get("highlight.dark_theme").cloned();
+        reporter.report(Error::Config(location, reason.to_string()));
+        valid = false;
+    }
+    if !valid {
+        return Vec::new();
+    }
+
+    let blog_clusters = session.load_clusters(config, reporter);
+    println!(
+        "{} blogs with {} tags found.",
+        blog_clusters.num_blog(),
+        blog_clusters.num_tag()
+    );
+
+    let inputs = inputs_hash(config, blog_clusters);
+    let context = Context::new(blog_clusters).with_site(&config.site);
+    let mut pages: Vec<(String, u64, Option<String>)> = Vec::new();
+    if let Some(template) = blog_template {
+        let template_key = template_hash(inputs, &config.blog_template);
+        let mut keys = HashMap::new();
+        let mut skipped = HashSet::new();
+        for blog in blog_clusters.get_blogs() {
+            let content_key = template_blog::content_key(blog, highlight);
+            let key = page_hash(&[template_key, content_key]);
+            // Pages warned about aren't skipped, their contents aren't cached
+            if cache.unchanged(&blog.path(), key, &config.output_dir) && cache.contains(content_key)
+            {
+                skipped.insert(&blog.source);
+                pages.push((blog.path(), key, None));
+            }
+            keys.insert(blog.path(), key);
+        }
+        let filled = template.fill_with(
+            context,
+            |x| !skipped.contains(&x.source),
+            cache,
+            config.jobs,
+            highlight,
+            reporter,
+        );
+        pages.extend(
+            filled
+                .into_iter()
+                .map(|(page, content)| (page.clone(), keys[&page], Some(content))),
+        );
+    }
+    // Listing pages are cheap to expand, but they are only compared with the
+    // output if their inputs changed
+    let mut listings = Vec::new();
+    if let Some(template) = cluster_template {
+        let key = template_hash(inputs, &config.cluster_template);
+        listings.extend(
+            template
+                .fill(context, reporter)
+                .into_iter()
+                .map(|x| (x, key)),
+        );
+    }
+    if let Some(template) = homepage_template {
+        let key = template_hash(inputs, &config.homepage_template);
+        listings.extend(
+            template
+                .fill(context, reporter)
+                .into_iter()
+                .map(|x| (x, key)),
+        );
+    }
+    if let Some(template) = tag_template {
+        let key = template_hash(inputs, &config.tag_template);
+        listings.extend(
+            template
+                .fill(context, reporter)
+                .into_iter()
+                .map(|x| (x, key)),
+        );
+    }
+    for ((page, content), key) in listings {
+        let content = (!cache.unchanged(&page, key, &config.output_dir)).then_some(content);
+        pages.push((page, key, content));
+    }
+    pages
+}
+
+// Hash of what every page is generated from: the site, the tags and metadata
+// of all blogs. Contents of blogs only go in the hashes of their own pages.
+fn inputs_hash(config: &Config, blog_clusters: &BlogClusters) -> u64 {
+    let mut hasher = Hasher::new();
+    hasher.write(format!("{:?}", config.site).as_bytes());
+    hasher.write(format!("{:?}", blog_clusters.get_tags()).as_bytes());
+    for blog in blog_clusters.get_blogs() {
+        let latex = !template_blog::extract_latex(&blog.content).1.is_empty();
+        let metadata = (
+            &blog.source,
+            &blog.title,
+            (blog.year, blog.month, blog.day),
+            &blog.tags,
+            &blog.slug,
+            &blog.preview,
+            &blog.extra,
+            latex,
+        );
+        hasher.write(format!("{:?}", metadata).as_bytes());
+    }
+    hasher.finish()
+}
+
+// Hash of the inputs and the files of a template
+fn template_hash(inputs: u64, template_path: &Path) -> u64 {
+    let mut hasher = Hasher::new();
+    hasher.write(&inputs.to_le_bytes());
+    for path in template_sources(template_path) {
+        hasher.write(path.to_string_lossy().as_bytes());
+        // Missing files are reported by the build
+        hasher.write(&fs::read(&path).unwrap_or_default());
+    }
+    hasher.finish()
+}
+
+fn page_hash(keys: &[u64]) -> u64 {
+    let mut hasher = Hasher::new();
+    for key in keys {
+        hasher.write(&key.to_le_bytes());
+    }
+    hasher.finish()
+}
+
+// Build pages whose inputs changed since they were written. They are only
+// written if they changed, keeping their modification time otherwise. Pages
+// written before but not generated anymore, e.g. of a removed blog, are
+// removed.
+fn build(config: &Config, session: &mut Session) -> i32 {
+    let mut reporter = Reporter::new();
+    let mut cache = Cache::load(&config.cache_dir);
+    let pages = generate(config, session, &mut cache, &mut reporter);
+    let (hits, misses) = cache.highlights.stats();
+    println!(
+        "{} code blocks highlighted, {} from the cache.",
+        misses, hits
+    );
+    // Nothing is written if anything is wrong
+    if reporter.print() {
+        return FAILURE;
+    }
+    if pages.iter().all(|x| x.2.is_none()) {
+        println!("Nothing changed since the last build.");
+    }
+
+    match fs::create_dir_all(&config.output_dir) {
+        Ok(_) => println!(
+            "Create direcotry \"{}\" if not exist.",
+            config.output_dir.display()
+        ),
         Err(err) => println!("Create directory failed: {}.", err),
     }
 
-    for (file_name, file_content) in blog_html_result {
-        let path = output_path.clone() + &file_name;
-        match fs::write(&path, file_content) {
-            Ok(_) => println!("Output to \"{}\" ok.", &path),
-            Err(err) => panic!("Write to \"{}\" failed: {}.", &path, err),
+    let mut unchanged = 0;
+    for (file_name, _, file_content) in pages.iter() {
+        let path = config.output_dir.join(file_name);
+        let Some(file_content) = file_content else {
+            unchanged += 1;
+            continue;
+        };
+        if fs::read(&path).is_ok_and(|x| x == file_content.as_bytes()) {
+            unchanged += 1;
+            continue;
+        }
+        // Tag pages live in a sub directory
+        let result = match path.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|_| fs::write(&path, file_content));
+        match result {
+            Ok(_) => println!("Output to \"{}\" ok.", path.display()),
+            Err(err) => reporter.report(Error::Io(Location::file(&path), err)),
+        }
+    }
+    if unchanged > 0 {
+        println!("{} pages unchanged.", unchanged);
+    }
+
+    let keys: BTreeMap<String, u64> = pages.into_iter().map(|x| (x.0, x.1)).collect();
+    if !reporter.has_errors() {
+        for page in cache.pages().filter(|x| !keys.contains_key(*x)) {
+            let path = config.output_dir.join(page);
+            match fs::remove_file(&path) {
+                Ok(_) => println!("Remove \"{}\" ok.", path.display()),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => reporter.report(Error::Io(Location::file(&path), err)),
+            }
+        }
+    }
+    let keys = (!reporter.has_errors()).then_some(&keys);
+    if let Err(err) = cache.save(keys) {
+        reporter.report(Error::Io(Location::file(&config.cache_dir), err));
+    }
+    exit_code(&mut reporter)
+}
+
+fn check(config: &Config) -> i32 {
+    let mut reporter = Reporter::new();
+    let mut cache = Cache::load(&config.cache_dir);
+    let pages = generate(config, &mut Session::default(), &mut cache, &mut reporter);
+    if !reporter.has_errors() {
+        println!("{} pages would be generated.", pages.len());
+    }
+    exit_code(&mut reporter)
+}
+
+fn list_tags(config: &Config) -> i32 {
+    let mut reporter = Reporter::new();
+    let mut session = Session::default();
+    let blog_clusters = session.load_clusters(config, &mut reporter);
+    for (i, tag) in blog_clusters.get_tags().iter().enumerate() {
+        let count = blog_clusters.get_tag_blogs(i).map_or(0, |x| x.len());
+        println!("{:>4}  {}", count, tag.name);
+    }
+    exit_code(&mut reporter)
+}
+
+// Days since 1970-01-01 to year, month and day, in the proleptic Gregorian
+// calendar.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// Offset of local time from UTC at the time, in seconds
+#[cfg(unix)]
+fn local_offset(seconds: i64) -> i64 {
+    let time = seconds as libc::time_t;
+    // Fields are all integers, zeros are fine
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // Unlike `localtime`, `localtime_r` doesn't share the result
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+// Time zone isn't known, UTC then
+#[cfg(not(unix))]
+fn local_offset(_: i64) -> i64 {
+    0
+}
+
+// Today in local time
+fn today() -> (i64, i64, i64) {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs() as i64);
+    civil_from_days((seconds + local_offset(seconds)).div_euclid(86400))
+}
+
+// Front matter and body of a new blog, the preview and content are
+// placeholders
+fn new_blog_md(title: &str, (year, month, day): (i64, i64, i64)) -> String {
+    format!(
+        "+++\ntitle = {}\ndate = {:04}-{:02}-{:02}\ntags = []\ndraft = true\n+++\n\n\
+         Preview of the blog.\n\n---\n\nContent of the blog.\n",
+        toml::Value::String(title.to_string()),
+        year,
+        month,
+        day
+    )
+}
+
+fn new_blog(config: &Config, title: &str) -> i32 {
+    if path_title(title).is_empty() {
+        eprintln!("error: title \"{}\" can't be a file name.", title);
+        return USAGE;
+    }
+    let path = config.blog_dir.join(format!("{}.md", path_title(title)));
+    if path.exists() {
+        eprintln!("error: \"{}\" already exists.", path.display());
+        return FAILURE;
+    }
+    let result = fs::create_dir_all(&config.blog_dir)
+        .and_then(|_| fs::write(&path, new_blog_md(title, today())));
+    match result {
+        Ok(_) => {
+            println!("Create \"{}\" ok.", path.display());
+            SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {}", Error::Io(Location::file(&path), err));
+            FAILURE
         }
     }
-    for (file_name, file_content) in homepage_html_result {
-        let path = output_path.clone() + &file_name;
-        match fs::write(&path, file_content) {
-            Ok(_) => println!("Output to \"{}\" ok.", &path),
-            Err(err) => panic!("Write to \"{}\" failed: {}.", &path, err),
+}
+
+fn highlight_css(config: &Config) -> i32 {
+    match template_blog::highlight_css(&config.highlight) {
+        Ok(css) => {
+            print!("{}", css);
+            SUCCESS
+        }
+        Err(reason) => {
+            eprintln!("error: {}", reason);
+            FAILURE
         }
     }
 }
+
+// Template with the layouts and partials it reads
+fn template_sources(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    // A broken template is reported by the build, its own file is watched
+    // till it's fixed
+    let expander = fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| Expander::load(path, &raw).ok());
+    if let Some(expander) = expander {
+        files.extend(expander.files().iter().cloned());
+    }
+    files
+}
+
+fn template_files(config: &Config) -> Vec<PathBuf> {
+    [
+        &config.homepage_template,
+        &config.blog_template,
+        &config.cluster_template,
+        &config.tag_template,
+    ]
+    .iter()
+    .flat_map(|x| template_sources(x))
+    .collect()
+}
+
+// Rebuild on changes forever, `rebuilt` is called after every rebuild. The
+// session is the one of the first build.
+fn watch_changes(config: &Config, mut session: Session, mut rebuilt: impl FnMut()) {
+    let roots = |config: &Config| {
+        let mut roots = vec![config.blog_dir.clone(), config.tags_path.clone()];
+        roots.extend(template_files(config));
+        roots
+    };
+    let mut watcher = Watcher::new(roots(config));
+    println!("Watching for changes, press Ctrl-C to stop.");
+    loop {
+        let changes = watcher.wait(Duration::from_millis(200));
+        for path in changes.paths() {
+            println!("\"{}\" changed.", path.display());
+        }
+        session.changed.extend(changes.paths().cloned());
+        // Only pages whose inputs changed are generated again
+        build(config, &mut session);
+        rebuilt();
+        watcher.set_roots(roots(config));
+    }
+}
+
+fn watch(config: &Config) -> i32 {
+    let mut session = Session::default();
+    build(config, &mut session);
+    watch_changes(config, session, || ());
+    SUCCESS
+}
+
+// Serve the output, open pages are reloaded after rebuilding on changes
+fn serve(config: &Config, port: u16) -> i32 {
+    // Problems are printed like `watch` does, pages are served once fixed
+    let mut session = Session::default();
+    build(config, &mut session);
+    let server = match Server::bind(&config.output_dir, port) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("error: can't serve at port {}: {}", port, err);
+            return FAILURE;
+        }
+    };
+    let reload = server.reload();
+    thread::spawn(move || server.run());
+    watch_changes(config, session, || reload.notify());
+    SUCCESS
+}
+
+fn run(subcommand: Subcommand, args: &[String], port: u16, config: &Config) -> i32 {
+    match subcommand {
+        Subcommand::Build => build(config, &mut Session::default()),
+        Subcommand::New => new_blog(config, &args[0]),
+        Subcommand::Check => check(config),
+        Subcommand::Tags => list_tags(config),
+        Subcommand::Serve => serve(config, port),
+        Subcommand::Watch => watch(config),
+        Subcommand::Fmt => inspect_templates(args, false),
+        Subcommand::Dump => inspect_templates(args, true),
+        Subcommand::HighlightCss => highlight_css(config),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (subcommand, args, port, flags) = match cli::parse(&args) {
+        Ok(Command::Run {
+            subcommand,
+            args,
+            port,
+            flags,
+        }) => (subcommand, args, port, flags),
+        Ok(Command::Help(subcommand)) => {
+            print!("{}", cli::usage(subcommand));
+            process::exit(SUCCESS);
+        }
+        Err(reason) => {
+            eprintln!("error: {}\nRun `blogen --help` for usage.", reason);
+            process::exit(USAGE);
+        }
+    };
+
+    // Settings in `blogen.toml`, overridden by environment variables and
+    // flags, see `config`.
+    dotenv::dotenv().ok();
+    let config = match Config::from_env(&flags) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(FAILURE);
+        }
+    };
+    process::exit(run(subcommand, &args, port, &config));
+}
+
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2000, 2, 29), civil_from_days(11016));
+        assert_eq!((2021, 4, 22), civil_from_days(18739));
+        assert_eq!((1969, 12, 31), civil_from_days(-1));
+    }
+
+    #[test]
+    fn test_new_blog_md() {
+        let md = new_blog_md("Say \"Hi\"", (2021, 4, 2));
+        assert_eq!(
+            md,
+            "+++\ntitle = 'Say \"Hi\"'\ndate = 2021-04-02\ntags = []\ndraft = true\n+++\n\n\
+             Preview of the blog.\n\n---\n\nContent of the blog.\n"
+        );
+        // It's a draft, so it's skipped without being checked
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_blogs(&[(PathBuf::from("say-hi.md"), md.clone())], &mut reporter);
+        assert!(!reporter.has_errors());
+        assert_eq!(0, clusters.num_blog());
+        // Published as it is once it's not a draft
+        let md = md.replace("draft = true", "draft = false");
+        clusters.add_blogs(&[(PathBuf::from("say-hi.md"), md)], &mut reporter);
+        assert!(!reporter.has_errors());
+        assert_eq!(1, clusters.num_blog());
+        assert_eq!(clusters.get_blogs()[0].preview, "Preview of the blog.");
+    }
+}
@@ -0,0 +1,478 @@
+//! Metadata of a blog in markdown.
+//!
+//! Two formats are accepted. The legacy one is three positional lines:
+//! ```txt
+//! Title
+//! 2000/9/27
+//! tag_a | tag_b | tag_c
+//! ---
+//! ```
+//! The other one is a fenced front matter block with named keys, in TOML
+//! (fenced by `+++`) or YAML (fenced by `---`):
+//! ```txt
+//! +++
+//! title = "Title"
+//! date = 2000-09-27
+//! tags = ["tag_a", "tag_b"]
+//! draft = false
+//! slug = "title"
+//! summary = "Used as preview."
+//! whatever = "Kept in extra."
+//! +++
+//! ```
+//! Either way the metadata is followed by the blog body.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{Error, Location};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub title: String,
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub tags: Vec<String>,
+    pub draft: bool,
+    pub slug: Option<String>,
+    pub summary: Option<String>,
+    // Keys we don't know, values are stringified
+    pub extra: BTreeMap<String, String>,
+    // Where the title, date, tags and slug are in the file, for diagnostics
+    pub title_line: usize,
+    pub date_line: usize,
+    pub tags_line: usize,
+    pub slug_line: usize,
+}
+
+// `2000/9/27` or `2000-09-27`, spaces around separators are permitted. Time
+// of a datetime like `2000-09-27T10:00:00` is ignored.
+fn parse_date(date: &str) -> Option<(i64, i64, i64)> {
+    let mut date = date.trim();
+    if date.contains(':') {
+        date = date.split(['T', 't', ' ']).next()?;
+    }
+    let sep = if date.contains('-') { '-' } else { '/' };
+    let time = date
+        .split(sep)
+        .map(|x| x.trim().parse::<i64>())
+        .collect::<Result<Vec<i64>, _>>()
+        .ok()?;
+    match time[..] {
+        [year, month, day] => Some((year, month, day)),
+        _ => None,
+    }
+}
+
+// Used for metadata we don't understand. Nested values are kept in their
+// source representation.
+fn toml_to_string(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(x) => x.clone(),
+        x => x.to_string(),
+    }
+}
+
+fn yaml_to_string(value: &serde_yaml::Value) -> String {
+    match value {
+        serde_yaml::Value::Null => String::new(),
+        serde_yaml::Value::Bool(x) => x.to_string(),
+        serde_yaml::Value::Number(x) => x.to_string(),
+        serde_yaml::Value::String(x) => x.clone(),
+        x => serde_yaml::to_string(x)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+// Line of the key in the whole file. Front matter starts at the second line,
+// after the fence.
+fn key_line(front_matter: &str, key: &str) -> usize {
+    front_matter
+        .lines()
+        .position(|line| {
+            line.trim_start()
+                .strip_prefix(key)
+                .is_some_and(|x| x.trim_start().starts_with(['=', ':']))
+        })
+        .map_or(1, |i| i + 2)
+}
+
+// Location of a byte offset in the front matter.
+fn offset_location(path: &Path, front_matter: &str, offset: usize) -> Location {
+    let before = &front_matter[..offset.min(front_matter.len())];
+    let line = before.matches('\n').count() + 2;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    Location::new(path, line, column)
+}
+
+// Parse the date and record where the known keys are.
+fn finish_front_matter(
+    path: &Path,
+    front_matter: &str,
+    mut metadata: Metadata,
+    date: Option<String>,
+) -> Result<Metadata, Error> {
+    metadata.title_line = key_line(front_matter, "title");
+    metadata.date_line = key_line(front_matter, "date");
+    metadata.tags_line = key_line(front_matter, "tags");
+    metadata.slug_line = key_line(front_matter, "slug");
+    let date = date.ok_or_else(|| {
+        Error::Metadata(Location::new(path, 1, 1), "Where is the date?".to_string())
+    })?;
+    (metadata.year, metadata.month, metadata.day) = parse_date(&date).ok_or_else(|| {
+        let mut location = Location::find(path, front_matter, metadata.date_line - 1, &date);
+        location.line = Some(metadata.date_line);
+        Error::InvalidDate(location, date.clone())
+    })?;
+    Ok(metadata)
+}
+
+fn parse_toml(path: &Path, front_matter: &str) -> Result<Metadata, Error> {
+    let table: toml::Table = front_matter.parse().map_err(|err: toml::de::Error| {
+        let offset = err.span().map_or(0, |x| x.start);
+        Error::Metadata(
+            offset_location(path, front_matter, offset),
+            format!("Invalid TOML front matter: {}", err.message()),
+        )
+    })?;
+    let invalid = |key: &str, reason: String| {
+        Error::Metadata(Location::new(path, key_line(front_matter, key), 1), reason)
+    };
+    let mut metadata = Metadata::default();
+    let mut date = None;
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("title", toml::Value::String(x)) => metadata.title = x,
+            ("date", toml::Value::String(x)) => date = Some(x),
+            ("date", toml::Value::Datetime(x)) => date = Some(x.to_string()),
+            ("tags", toml::Value::Array(x)) => {
+                metadata.tags = x
+                    .into_iter()
+                    .map(|x| match x {
+                        toml::Value::String(x) => Ok(x),
+                        _ => Err(invalid("tags", "Tags should be strings.".to_string())),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            ("draft", toml::Value::Boolean(x)) => metadata.draft = x,
+            ("slug", toml::Value::String(x)) => metadata.slug = Some(x),
+            ("summary", toml::Value::String(x)) => metadata.summary = Some(x),
+            ("title" | "date" | "tags" | "draft" | "slug" | "summary", _) => {
+                return Err(invalid(
+                    &key,
+                    format!("Invalid type of \"{}\" in front matter.", key),
+                ))
+            }
+            (_, x) => {
+                metadata.extra.insert(key, toml_to_string(&x));
+            }
+        }
+    }
+    finish_front_matter(path, front_matter, metadata, date)
+}
+
+fn parse_yaml(path: &Path, front_matter: &str) -> Result<Metadata, Error> {
+    let mapping: serde_yaml::Mapping = serde_yaml::from_str(front_matter).map_err(|err| {
+        let location = match err.location() {
+            Some(x) => offset_location(path, front_matter, x.index()),
+            None => Location::new(path, 1, 1),
+        };
+        Error::Metadata(location, format!("Invalid YAML front matter: {}", err))
+    })?;
+    let invalid = |key: &str, reason: String| {
+        Error::Metadata(Location::new(path, key_line(front_matter, key), 1), reason)
+    };
+    let mut metadata = Metadata::default();
+    let mut date = None;
+    for (key, value) in mapping {
+        let key = match key {
+            serde_yaml::Value::String(x) => x,
+            x => yaml_to_string(&x),
+        };
+        match (key.as_str(), value) {
+            ("title", serde_yaml::Value::String(x)) => metadata.title = x,
+            ("date", serde_yaml::Value::String(x)) => date = Some(x),
+            ("tags", serde_yaml::Value::Sequence(x)) => {
+                metadata.tags = x
+                    .into_iter()
+                    .map(|x| match x {
+                        serde_yaml::Value::String(x) => Ok(x),
+                        _ => Err(invalid("tags", "Tags should be strings.".to_string())),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            ("draft", serde_yaml::Value::Bool(x)) => metadata.draft = x,
+            ("slug", serde_yaml::Value::String(x)) => metadata.slug = Some(x),
+            ("summary", serde_yaml::Value::String(x)) => metadata.summary = Some(x),
+            ("title" | "date" | "tags" | "draft" | "slug" | "summary", _) => {
+                return Err(invalid(
+                    &key,
+                    format!("Invalid type of \"{}\" in front matter.", key),
+                ))
+            }
+            (_, x) => {
+                metadata.extra.insert(key, yaml_to_string(&x));
+            }
+        }
+    }
+    finish_front_matter(path, front_matter, metadata, date)
+}
+
+// The legacy three positional lines
+fn parse_lines(path: &Path, lines: &str) -> Result<Metadata, Error> {
+    let mut line_it = lines.lines();
+    let missing = |line: usize, reason: &str| {
+        Error::Metadata(Location::new(path, line, 1), reason.to_string())
+    };
+
+    // First line is title
+    let title = line_it
+        .next()
+        .ok_or_else(|| missing(1, "Where is the title?"))?
+        .trim();
+
+    // Second line is time: `2000 / 9 / 27`
+    let date = line_it
+        .next()
+        .ok_or_else(|| missing(2, "Where is the time?"))?
+        .trim();
+    let (year, month, day) = parse_date(date).ok_or_else(|| {
+        Error::InvalidDate(Location::find(path, lines, 2, date), date.to_string())
+    })?;
+
+    // Third line is tags: `aaa | bbb | ccc | ...`
+    let tags = line_it
+        .next()
+        .ok_or_else(|| missing(3, "Where are the tags?"))?
+        .split('|')
+        .map(|x| x.trim().to_string()) // permits arbitrary spaces between tag and seperator
+        .collect();
+
+    Ok(Metadata {
+        title: title.to_string(),
+        year,
+        month,
+        day,
+        tags,
+        title_line: 1,
+        date_line: 2,
+        tags_line: 3,
+        slug_line: 1,
+        ..Default::default()
+    })
+}
+
+// Split the fenced block out if the first line is the given fence. Return the
+// block and the remaining part.
+fn split_fenced<'a>(
+    path: &Path,
+    blog: &'a str,
+    fence: &str,
+) -> Result<Option<(&'a str, &'a str)>, Error> {
+    let mut lines = blog.split_inclusive('\n');
+    let begin = match lines.next() {
+        Some(line) if line.trim() == fence => line.len(),
+        _ => return Ok(None),
+    };
+    let mut end = begin;
+    for line in lines {
+        if line.trim() == fence {
+            return Ok(Some((&blog[begin..end], &blog[end + line.len()..])));
+        }
+        end += line.len();
+    }
+    Err(Error::Metadata(
+        Location::new(path, 1, 1),
+        format!("Front matter fenced by \"{}\" is not closed.", fence),
+    ))
+}
+
+// Split the text at the first separator: a line of `---` that is not inside a
+// fenced code block. Return the parts before and after the separator line.
+pub fn split_separator(text: &str) -> Option<(&str, &str)> {
+    // Opening fence of the code block we are in, e.g. "```" or "~~~~"
+    let mut fence: Option<&str> = None;
+    let mut begin = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        match fence {
+            Some(open) => {
+                // Closing fence should be at least as long as the opening one
+                // and has no info string.
+                if trimmed.starts_with(open) && trimmed.chars().all(|x| open.starts_with(x)) {
+                    fence = None;
+                }
+            }
+            None => {
+                if trimmed == "---" {
+                    return Some((&text[..begin], &text[begin + line.len()..]));
+                }
+                for marker in ['`', '~'] {
+                    let len = trimmed.chars().take_while(|&x| x == marker).count();
+                    if len >= 3 {
+                        fence = Some(&trimmed[..len]);
+                    }
+                }
+            }
+        }
+        begin += line.len();
+    }
+    None
+}
+
+// Parse metadata of a blog, return the metadata and the rest of the blog.
+pub fn parse_metadata<'a>(path: &Path, blog: &'a str) -> Result<(Metadata, &'a str), Error> {
+    let blog = blog.trim_start_matches('\u{feff}');
+    if let Some((front_matter, body)) = split_fenced(path, blog, "+++")? {
+        Ok((parse_toml(path, front_matter)?, body))
+    } else if let Some((front_matter, body)) = split_fenced(path, blog, "---")? {
+        Ok((parse_yaml(path, front_matter)?, body))
+    } else {
+        let (lines, body) = split_separator(blog).ok_or_else(|| {
+            Error::Metadata(Location::file(path), "Where is the meta data?".to_string())
+        })?;
+        Ok((parse_lines(path, lines)?, body))
+    }
+}
+
+#[cfg(test)]
+mod metadata_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(Some((2000, 9, 27)), parse_date("2000/9/27"));
+        assert_eq!(Some((2000, 9, 27)), parse_date(" 2000 / 9 / 27 "));
+        assert_eq!(Some((2000, 9, 27)), parse_date("2000-09-27"));
+        assert!(parse_date("2000/9").is_none());
+        assert!(parse_date("2000/9/x").is_none());
+        assert!(parse_date("2000/9/27/1").is_none());
+        assert_eq!(Some((2000, 9, 27)), parse_date("2000-09-27T10:00:00"));
+        assert_eq!(Some((2000, 9, 27)), parse_date("2000-09-27 10:00:00+08:00"));
+        assert!(parse_date("10:00:00").is_none());
+    }
+
+    #[test]
+    fn test_split_separator() {
+        assert_eq!(Some(("a\n", "b")), split_separator("a\n---\nb"));
+        assert_eq!(
+            Some(("a\r\n", "b\r\n")),
+            split_separator("a\r\n --- \r\nb\r\n")
+        );
+        assert_eq!(Some(("", "b")), split_separator("---\nb"));
+        assert_eq!(Some(("a\n", "")), split_separator("a\n---"));
+        assert_eq!(
+            Some(("a\n", "b\n---\nc")),
+            split_separator("a\n---\nb\n---\nc")
+        );
+        // Not a standalone line
+        assert_eq!(None, split_separator("a --- b\n----\n-- -\n"));
+        // In fenced code
+        let text = "```yaml\n---\n```\n~~~~\n---\n~~~\n---\n~~~~\n---\nb";
+        assert_eq!(
+            Some(("```yaml\n---\n```\n~~~~\n---\n~~~\n---\n~~~~\n", "b")),
+            split_separator(text)
+        );
+        assert_eq!(None, split_separator("```\n---\n"));
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let (metadata, body) = parse_metadata(
+            Path::new("a.md"),
+            "Test Blog\n2000/9/27\nlife | work\n---\npreview\n---\ncontent",
+        )
+        .unwrap();
+        assert_eq!(metadata.title, "Test Blog");
+        assert_eq!((metadata.year, metadata.month, metadata.day), (2000, 9, 27));
+        assert_eq!(metadata.tags, ["life", "work"]);
+        assert!(!metadata.draft);
+        assert_eq!(body, "preview\n---\ncontent");
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let (metadata, body) = parse_metadata(
+            Path::new("a.md"),
+            "+++
+title = \"Test Blog\"
+date = 2000-09-27
+tags = [\"life\", \"work\"]
+draft = true
+slug = \"test\"
+summary = \"lol\"
+weather = \"sunny\"
+mood = 7
++++
+content",
+        )
+        .unwrap();
+        assert_eq!(metadata.title, "Test Blog");
+        assert_eq!((metadata.year, metadata.month, metadata.day), (2000, 9, 27));
+        assert_eq!(metadata.tags, ["life", "work"]);
+        assert!(metadata.draft);
+        assert_eq!(metadata.slug.as_deref(), Some("test"));
+        assert_eq!(metadata.summary.as_deref(), Some("lol"));
+        assert_eq!(metadata.extra["weather"], "sunny");
+        assert_eq!(metadata.extra["mood"], "7");
+        assert_eq!(body, "content");
+
+        // Date of a datetime
+        let (metadata, _) = parse_metadata(
+            Path::new("a.md"),
+            "+++\ntitle = \"a\"\ndate = 2000-09-27T10:00:00\n+++\n",
+        )
+        .unwrap();
+        assert_eq!((metadata.year, metadata.month, metadata.day), (2000, 9, 27));
+        assert!(parse_metadata(
+            Path::new("a.md"),
+            "+++\ntitle = \"a\"\ndate = 10:00:00\n+++\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_yaml() {
+        let (metadata, body) = parse_metadata(
+            Path::new("a.md"),
+            "---
+title: Test Blog
+date: 2000/9/27
+tags:
+  - life
+  - work
+weather: sunny
+---
+preview
+---
+content",
+        )
+        .unwrap();
+        assert_eq!(metadata.title, "Test Blog");
+        assert_eq!((metadata.year, metadata.month, metadata.day), (2000, 9, 27));
+        assert_eq!(metadata.tags, ["life", "work"]);
+        assert!(!metadata.draft);
+        assert_eq!(metadata.slug, None);
+        assert_eq!(metadata.extra["weather"], "sunny");
+        assert_eq!(body, "preview\n---\ncontent");
+    }
+
+    #[test]
+    fn test_parse_invalid_front_matter() {
+        assert!(parse_metadata(Path::new("a.md"), "+++\ntitle = \"a\"\n+++\n").is_err());
+        assert!(parse_metadata(
+            Path::new("a.md"),
+            "+++\ntitle = 1\ndate = \"2000/9/27\"\n+++\n"
+        )
+        .is_err());
+        assert!(parse_metadata(
+            Path::new("a.md"),
+            "+++\ntitle = \"a\"\ndate = \"2000/9/27\"\n"
+        )
+        .is_err());
+        assert!(parse_metadata(Path::new("a.md"), "---\ntitle: [\n---\n").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! Serve the generated site for preview.
+//!
+//! A tiny HTTP server, only `GET` and `HEAD` of files in the output directory.
+//! It's for looking at the site locally, don't put it on the internet.
+//!
+//! HTML pages get a script listening on `RELOAD_PATH` for server-sent events,
+//! open tabs are reloaded once `Reload::notify` is called after a rebuild.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Can't clash with generated pages, they are never named like this
+pub const RELOAD_PATH: &str = "/__blogen/reload";
+
+// How often an idle reload stream is pinged, to notice closed tabs
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+// Version of the site, bumped by every rebuild
+#[derive(Default)]
+pub struct Reload {
+    version: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl Reload {
+    pub fn version(&self) -> u64 {
+        *self.version.lock().unwrap()
+    }
+
+    pub fn notify(&self) {
+        *self.version.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+
+    // Wait for a version newer than `since`, the current version is returned
+    // anyway after `timeout`
+    fn wait(&self, since: u64, timeout: Duration) -> u64 {
+        let version = self.version.lock().unwrap();
+        let (version, _) = self
+            .changed
+            .wait_timeout_while(version, timeout, |x| *x <= since)
+            .unwrap();
+        *version
+    }
+}
+
+// Reloads the page when the site is newer than the version it was served at
+fn reload_script(version: u64) -> String {
+    format!(
+        "<script>new EventSource(\"{}?since={}\").onmessage = () => location.reload();</script>",
+        RELOAD_PATH, version
+    )
+}
+
+// Put the script before `</body>`, or at the end if there is none
+fn inject(html: &[u8], script: &str) -> Vec<u8> {
+    let html = String::from_utf8_lossy(html);
+    let (before, after) = match html.rfind("</body>") {
+        Some(i) => html.split_at(i),
+        None => (html.as_ref(), ""),
+    };
+    format!("{}{}{}", before, script, after).into_bytes()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("txt" | "md") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+// `%E4%B8%AD` to `中`, None if it's not valid UTF-8
+fn percent_decode(s: &str) -> Option<String> {
+    let s = s.as_bytes();
+    let mut result = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(s.get(i + 1..i + 3)?).ok()?;
+                result.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            x => {
+                result.push(x);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(result).ok()
+}
+
+// File of the request target in `root`. Targets escaping the root are
+// rejected, directories are served by their `index.html`.
+fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+    let target = target.split(['?', '#']).next().unwrap_or("");
+    let target = percent_decode(target)?;
+    let mut path = root.to_path_buf();
+    for component in Path::new(target.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(x) => path.push(x),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    Some(path)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    head: bool,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    if !head {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+// Events until the site is rebuilt or the tab goes away
+fn stream_reload(stream: &mut TcpStream, reload: &Reload, since: u64) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    stream.flush()?;
+    loop {
+        if reload.wait(since, PING_INTERVAL) > since {
+            write!(stream, "data: reload\n\n")?;
+            return stream.flush();
+        }
+        // A comment, writing fails once the tab is closed
+        write!(stream, ": ping\n\n")?;
+        stream.flush()?;
+    }
+}
+
+fn handle(mut stream: TcpStream, root: &Path, reload: &Reload) -> io::Result<()> {
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    reader.read_line(&mut request_line)?;
+    // Headers are not used, but should be consumed
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let head = method == "HEAD";
+    if method != "GET" && !head {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"405",
+            head,
+        );
+    }
+    if let Some(query) = target.strip_prefix(RELOAD_PATH) {
+        let since = query
+            .strip_prefix("?since=")
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_else(|| reload.version());
+        return stream_reload(&mut stream, reload, since);
+    }
+    match resolve(root, target).map(|x| (fs::read(&x), x)) {
+        Some((Ok(body), path)) if content_type(&path).starts_with("text/html") => {
+            let body = inject(&body, &reload_script(reload.version()));
+            respond(&mut stream, "200 OK", content_type(&path), &body, head)
+        }
+        Some((Ok(body), path)) => respond(&mut stream, "200 OK", content_type(&path), &body, head),
+        Some((Err(_), _)) => respond(&mut stream, "404 Not Found", "text/plain", b"404", head),
+        None => respond(&mut stream, "400 Bad Request", "text/plain", b"400", head),
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    root: PathBuf,
+    reload: Arc<Reload>,
+}
+
+impl Server {
+    // Listen on the local port, for files in `root`
+    pub fn bind(root: &Path, port: u16) -> io::Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(("127.0.0.1", port))?,
+            root: root.to_path_buf(),
+            reload: Arc::default(),
+        })
+    }
+
+    // Notify it after rebuilding to reload open pages
+    pub fn reload(&self) -> Arc<Reload> {
+        self.reload.clone()
+    }
+
+    // Serve forever, every connection is handled in its own thread
+    pub fn run(self) {
+        println!(
+            "Serving \"{}\" at http://{}/, press Ctrl-C to stop.",
+            self.root.display(),
+            self.listener
+                .local_addr()
+                .map_or("127.0.0.1".to_string(), |x| x.to_string())
+        );
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let root = self.root.clone();
+            let reload = self.reload.clone();
+            thread::spawn(move || {
+                // The browser went away, nothing to do
+                let _ = handle(stream, &root, &reload);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod serve_tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b").unwrap(), "a b");
+        assert_eq!(percent_decode("%E4%B8%AD%E6%96%87").unwrap(), "中文");
+        assert_eq!(percent_decode("%e4%b8%ad").unwrap(), "中");
+        assert!(percent_decode("%E4%B8").is_none());
+        assert!(percent_decode("%zz").is_none());
+        assert!(percent_decode("%2").is_none());
+    }
+
+    #[test]
+    fn test_inject() {
+        assert_eq!(
+            inject(b"<body>a</body></html>", "<script></script>"),
+            b"<body>a<script></script></body></html>"
+        );
+        assert_eq!(inject(b"a", "<script></script>"), b"a<script></script>");
+    }
+
+    #[test]
+    fn test_serve() {
+        let dir = std::env::temp_dir().join(format!("blogen-serve-{}", std::process::id()));
+        fs::create_dir_all(dir.join("tags")).unwrap();
+        fs::write(dir.join("index.html"), "home").unwrap();
+        fs::write(dir.join("tags/中文.html"), "tag").unwrap();
+
+        assert_eq!(resolve(&dir, "/"), Some(dir.join("index.html")));
+        assert_eq!(
+            resolve(&dir, "/tags/a.html?x=1"),
+            Some(dir.join("tags/a.html"))
+        );
+        assert_eq!(resolve(&dir, "/../secret"), None);
+        assert_eq!(resolve(&dir, "/tags/%2e%2e/%2e%2e/secret"), None);
+
+        let server = Server::bind(&dir, 0).unwrap();
+        let address = server.listener.local_addr().unwrap();
+        let reload = server.reload();
+        thread::spawn(move || server.run());
+        let get = move |request: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("GET /tags/%E4%B8%AD%E6%96%87.html HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(response.ends_with(&format!("\r\n\r\ntag{}", reload_script(0))));
+        assert!(get("GET /nope.html HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(get("POST / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+
+        // Pages served before a rebuild are reloaded
+        reload.notify();
+        let response = get("GET /__blogen/reload?since=0 HTTP/1.1\r\n\r\n");
+        assert!(response.contains("Content-Type: text/event-stream\r\n"));
+        assert!(response.ends_with("\r\n\r\ndata: reload\n\n"));
+        assert!(get("GET / HTTP/1.1\r\n\r\n").ends_with(&reload_script(1)));
+        let waiting = thread::spawn(move || get("GET /__blogen/reload?since=1 HTTP/1.1\r\n\r\n"));
+        thread::sleep(Duration::from_millis(50));
+        reload.notify();
+        assert!(waiting.join().unwrap().ends_with("data: reload\n\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,14 +1,22 @@
-use crate::blog_clusters::BlogClusters;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::error::{Error, Reporter};
+use crate::expander::Context;
 
 // Implemented by templates
 pub trait HTMLTemplate {
-    // Try to load html template from string
-    fn load(template_raw: &str) -> Result<Self, String>
+    // Try to load html template from string and check it against the schema of
+    // the page, the path is used for diagnostics
+    fn load(template_path: &Path, template_raw: &str) -> Result<Self, Error>
     where
         Self: std::marker::Sized;
 
-    // Return file name and file content
-    fn fill(&self, blog_clusters: &BlogClusters) -> Vec<(String, String)>;
+    // Return file name and file content, problems found are reported. Pages
+    // are expanded in the context given, which has the blogs and the site.
+    fn fill(&self, context: Context, reporter: &mut Reporter) -> Vec<(String, String)>;
 }
 
 // Fit average blog titles in webpage path. Used for path/filename generation
@@ -39,6 +47,48 @@ pub fn path_title<T: AsRef<str>>(title: T) -> String {
         })
 }
 
+// Path of the listing page of a tag, relative to the output directory.
+pub fn tag_path<T: AsRef<str>>(tag_name: T) -> String {
+    format!("tags/{}.html", path_title(tag_name))
+}
+
+// Threads to use for `jobs`, 0 means one per core
+pub fn threads(jobs: usize) -> usize {
+    match jobs {
+        0 => thread::available_parallelism().map_or(1, |x| x.get()),
+        x => x,
+    }
+}
+
+// `items.iter().map(f)` on `jobs` threads, results are in the order of items.
+// Items are taken one by one, so a slow one doesn't hold up a whole batch.
+pub fn par_map<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = threads(jobs).min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect()
+}
+
 #[cfg(test)]
 mod shared_tests {
     use super::*;
@@ -58,4 +108,23 @@ mod shared_tests {
         assert_eq!("烫烫烫", path_title("烫烫烫"));
         assert_eq!("-😄-", path_title("-😄-"));
     }
+
+    #[test]
+    fn test_tag_path() {
+        assert_eq!("tags/rust.html", tag_path("Rust"));
+        assert_eq!("tags/machine-learning.html", tag_path("Machine Learning"));
+        assert_eq!("tags/c++.html", tag_path("C++"));
+    }
+
+    #[test]
+    fn test_par_map() {
+        let items: Vec<u64> = (0..100).collect();
+        let squares: Vec<u64> = items.iter().map(|x| x * x).collect();
+        for jobs in [0, 1, 3, 200] {
+            assert_eq!(squares, par_map(&items, jobs, |x| x * x));
+        }
+        assert!(par_map(&[] as &[u64], 4, |x| *x).is_empty());
+        assert_eq!(threads(3), 3);
+        assert!(threads(0) >= 1);
+    }
 }
@@ -0,0 +1,658 @@
+//! Slots in template contents, filled from the context on expansion.
+//!
+//! ```txt
+//! {{ blog.title }}
+//! {{ blog.date | date "%Y-%m-%d" }}
+//! {{ blog.preview | truncate 100 | escape }}
+//! ```
+//! Filters are applied from left to right, `escape` goes last. The old `_slot_of_blog_title` form
+//! is the same as `{{ blog.title }}`.
+//!
+//! Values are escaped according to where they are put: `<`, `>` and `&` are
+//! escaped in text, quotes are escaped too in quoted attribute values, and
+//! white spaces, `=` and backticks in unquoted ones. In `script` and `style`
+//! elements, punctuation is escaped the way of JavaScript or CSS strings, so
+//! values are safe in string literals there. Where is kept track of as the
+//! page is generated, see `Html`. Pre-rendered html like `blog.content` opts
+//! out with the `raw` filter, a slot with the `escape` filter isn't escaped
+//! again.
+
+use memchr::memmem;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::borrow::Cow;
+
+use crate::template_blog::html_escape;
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    Text(Cow<'a, str>),
+    // year, month, day
+    Date(u16, u16, u16),
+}
+
+impl<'a> Value<'a> {
+    fn into_text(self) -> Cow<'a, str> {
+        match self {
+            Value::Text(x) => x,
+            Value::Date(year, month, day) => format!("{}/{}/{}", year, month, day).into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    // strftime-like: %Y %m %d %-m %-d %b %B %%
+    Date(String),
+    // Pad with zeros to the width
+    Pad(usize),
+    Upper,
+    // Keep at most the number of chars, the "..." ending a cut text included
+    Truncate(usize),
+    UrlEncode,
+    // Only as the last filter, or the filters after it would change the
+    // escaped text
+    Escape,
+    // Not escaped automatically
+    Raw,
+}
+
+fn format_date(format: &str, year: u16, month: u16, day: u16) -> Result<String, String> {
+    let month_name = MONTHS[(month as usize).saturating_sub(1) % 12];
+    let mut result = String::with_capacity(format.len() + 8);
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => result.push_str(&year.to_string()),
+            Some('m') => result.push_str(&format!("{:02}", month)),
+            Some('d') => result.push_str(&format!("{:02}", day)),
+            Some('b') => result.push_str(&month_name[..3]),
+            Some('B') => result.push_str(month_name),
+            Some('%') => result.push('%'),
+            Some('-') => match chars.next() {
+                Some('m') => result.push_str(&month.to_string()),
+                Some('d') => result.push_str(&day.to_string()),
+                _ => return Err(format!("unknown date format \"{}\".", format)),
+            },
+            _ => return Err(format!("unknown date format \"{}\".", format)),
+        }
+    }
+    Ok(result)
+}
+
+fn url_encode(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for &byte in s.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                result.push(byte as char)
+            }
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    result
+}
+
+impl Filter {
+    // `name` or `name argument`, argument could be quoted
+    fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let (name, arg) = match s.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim().trim_matches('"'))),
+            None => (s, None),
+        };
+        let number = |default: Option<usize>| match arg {
+            Some(x) => x
+                .parse::<usize>()
+                .map_err(|_| format!("\"{}\" filter needs a number, found \"{}\".", name, x)),
+            None => default.ok_or_else(|| format!("\"{}\" filter needs a number.", name)),
+        };
+        let filter = match name {
+            "date" => {
+                let format = arg.unwrap_or("%Y/%-m/%-d");
+                // Find bad format early
+                format_date(format, 2000, 1, 1)?;
+                Filter::Date(format.to_string())
+            }
+            "pad" => Filter::Pad(number(Some(2))?),
+            "upper" => Filter::Upper,
+            "truncate" => Filter::Truncate(number(None)?),
+            "urlencode" => Filter::UrlEncode,
+            "escape" => Filter::Escape,
+            "raw" => Filter::Raw,
+            _ => return Err(format!("unknown filter \"{}\".", name)),
+        };
+        Ok(filter)
+    }
+
+    fn apply<'a>(&self, value: Value<'a>) -> Result<Value<'a>, String> {
+        let text = match (self, value) {
+            (Filter::Date(format), Value::Date(year, month, day)) => {
+                format_date(format, year, month, day)?
+            }
+            (Filter::Date(_), Value::Text(x)) => {
+                return Err(format!("\"date\" filter needs a date, found \"{}\".", x))
+            }
+            (Filter::Pad(width), value) => format!("{:0>width$}", value.into_text(), width = width),
+            (Filter::Upper, value) => value.into_text().to_uppercase(),
+            (Filter::Truncate(len), value) => {
+                let text = value.into_text();
+                if text.chars().nth(*len).is_none() {
+                    return Ok(Value::Text(text));
+                }
+                // Too short for the ellipsis, just cut
+                let (kept, ellipsis) = match len.checked_sub(3) {
+                    Some(kept) => (kept, "..."),
+                    None => (*len, ""),
+                };
+                let end = text.char_indices().nth(kept).map_or(text.len(), |x| x.0);
+                format!("{}{}", &text[..end], ellipsis)
+            }
+            (Filter::UrlEncode, value) => url_encode(&value.into_text()),
+            (Filter::Escape, value) => html_escape(value.into_text()),
+            (Filter::Raw, value) => return Ok(value),
+        };
+        Ok(Value::Text(text.into()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slot {
+    // e.g. `blog.title`
+    pub name: String,
+    pub filters: Vec<Filter>,
+    // Where it begins in the text parsed, in bytes
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Text(String),
+    Slot(Slot),
+}
+
+// Split the content into texts and slots, done once when template is loaded.
+// Errors come with where the slot begins.
+pub fn parse_slots(text: &str) -> Result<Vec<Segment>, (usize, String)> {
+    static RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\{\{(.*?)\}\}|_slot_of_(blog|tag)_([a-z]+)").unwrap());
+    let mut segments = Vec::new();
+    let mut begin = 0;
+    for cap in RE.captures_iter(text) {
+        let range = cap.get(0).unwrap().range();
+        if begin < range.start {
+            segments.push(Segment::Text(text[begin..range.start].to_string()));
+        }
+        begin = range.end;
+        let slot = match cap.get(1) {
+            Some(inner) => {
+                let mut parts = inner.as_str().split('|');
+                let name = parts.next().unwrap().trim();
+                if name.is_empty() {
+                    return Err((range.start, format!("empty slot \"{}\".", &cap[0])));
+                }
+                let filters: Vec<Filter> = parts
+                    .map(Filter::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|reason| (range.start, reason))?;
+                if let Some((_, rest)) = filters.split_last() {
+                    if rest.contains(&Filter::Escape) {
+                        let reason =
+                            format!("\"escape\" should be the last filter in \"{}\".", &cap[0]);
+                        return Err((range.start, reason));
+                    }
+                }
+                Slot {
+                    name: name.to_string(),
+                    filters,
+                    offset: range.start,
+                }
+            }
+            None => Slot {
+                name: format!("{}.{}", &cap[2], &cap[3]),
+                // Old templates expect html of the content
+                filters: if &cap[3] == "content" {
+                    vec![Filter::Raw]
+                } else {
+                    Vec::new()
+                },
+                offset: range.start,
+            },
+        };
+        segments.push(Segment::Slot(slot));
+    }
+    if begin < text.len() {
+        segments.push(Segment::Text(text[begin..].to_string()));
+    }
+    Ok(segments)
+}
+
+// Escape `<`, `>` and `&`, enough for text.
+fn text_escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '&' => result.push_str("&amp;"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+// Escape `html_escape` leaves, so the value can't end an unquoted attribute
+// value.
+fn unquoted_escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for ch in html_escape(s).chars() {
+        match ch {
+            ' ' | '\t' | '\n' | '\r' | '\x0c' | '=' | '`' => {
+                result.push_str(&format!("&#{};", ch as u32))
+            }
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+// Escape ASCII punctuation and control characters in a JavaScript string, or
+// a CSS one if `css`. Quotes, backslashes and `</` can't end the string or the
+// element then.
+fn script_escape(s: &str, css: bool) -> String {
+    let mut result = String::with_capacity(s.len());
+    for ch in s.chars() {
+        if !ch.is_ascii() || ch.is_ascii_alphanumeric() || " ,.-_".contains(ch) {
+            result.push(ch);
+        } else if css {
+            // Six digits, so the next character isn't taken as one
+            result.push_str(&format!("\\{:06X}", ch as u32));
+        } else {
+            result.push_str(&format!("\\u{:04X}", ch as u32));
+        }
+    }
+    result
+}
+
+// Elements whose content is raw text, a `<` in it doesn't start a tag
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Mode {
+    #[default]
+    Text,
+    // In a tag, `raw` is the name of the element if its content is raw text
+    Tag {
+        raw: Option<&'static str>,
+    },
+    // In a quoted attribute value
+    Quoted {
+        quote: u8,
+        raw: Option<&'static str>,
+    },
+    Comment,
+    // Content of a raw text element, till its end tag
+    RawText(&'static str),
+}
+
+// Html of a page being generated. Where its end is, e.g. in a tag, is kept
+// track of as it grows, so slots are escaped for it.
+#[derive(Debug, Default)]
+pub struct Html {
+    pub text: String,
+    mode: Mode,
+    // Bytes of the text scanned for the mode, the rest can't be decided yet,
+    // e.g. a `<` at the end could start a tag or a comment
+    scanned: usize,
+}
+
+impl Html {
+    pub fn push_str(&mut self, s: &str) {
+        self.text.push_str(s);
+    }
+
+    // Escape the value for where the end is
+    fn escape(&mut self, value: &str) -> String {
+        self.scan();
+        match self.mode {
+            Mode::Text | Mode::Comment => text_escape(value),
+            Mode::Tag { .. } => unquoted_escape(value),
+            Mode::Quoted { .. } => html_escape(value),
+            Mode::RawText(name) => script_escape(value, name == "style"),
+        }
+    }
+
+    fn scan(&mut self) {
+        let bytes = self.text.as_bytes();
+        let mut i = self.scanned;
+        while i < bytes.len() {
+            let rest = &bytes[i..];
+            match self.mode {
+                Mode::Text => {
+                    let Some(lt) = memchr::memchr(b'<', rest) else {
+                        i = bytes.len();
+                        break;
+                    };
+                    i += lt;
+                    let rest = &bytes[i..];
+                    if rest.starts_with(b"<!--") {
+                        self.mode = Mode::Comment;
+                        i += 4;
+                        continue;
+                    }
+                    let end_tag = rest.get(1) == Some(&b'/');
+                    let name_begin = 1 + end_tag as usize;
+                    let name_len = rest[name_begin..]
+                        .iter()
+                        .take_while(|x| x.is_ascii_alphanumeric())
+                        .count();
+                    // Name could go on, or `<!-` become a comment
+                    if name_begin + name_len == rest.len() || b"<!--".starts_with(rest) {
+                        break;
+                    }
+                    // A `<` not starting a tag is text
+                    if name_len == 0 || !rest[name_begin].is_ascii_alphabetic() {
+                        i += 1;
+                        continue;
+                    }
+                    let name = &rest[name_begin..name_begin + name_len];
+                    let raw = RAW_TEXT_ELEMENTS
+                        .iter()
+                        .find(|x| !end_tag && x.as_bytes().eq_ignore_ascii_case(name))
+                        .copied();
+                    self.mode = Mode::Tag { raw };
+                    i += name_begin + name_len;
+                }
+                Mode::Tag { raw } => {
+                    match rest[0] {
+                        b'>' => self.mode = raw.map_or(Mode::Text, Mode::RawText),
+                        quote @ (b'"' | b'\'') => self.mode = Mode::Quoted { quote, raw },
+                        _ => (),
+                    }
+                    i += 1;
+                }
+                Mode::Quoted { quote, raw } => match memchr::memchr(quote, rest) {
+                    Some(x) => {
+                        self.mode = Mode::Tag { raw };
+                        i += x + 1;
+                    }
+                    None => i = bytes.len(),
+                },
+                Mode::Comment => match memmem::find(rest, b"-->") {
+                    Some(x) => {
+                        self.mode = Mode::Text;
+                        i += x + 3;
+                    }
+                    // `--` at the end could be closed later
+                    None => {
+                        i = bytes.len().saturating_sub(2).max(i);
+                        break;
+                    }
+                },
+                Mode::RawText(name) => {
+                    let Some(x) = memmem::find(rest, b"</") else {
+                        // `<` at the end could start the end tag
+                        i = bytes.len().saturating_sub(1).max(i);
+                        break;
+                    };
+                    let tag = &rest[x + 2..];
+                    let name = name.as_bytes();
+                    if tag.len() <= name.len() {
+                        i += x;
+                        break;
+                    }
+                    if tag[..name.len()].eq_ignore_ascii_case(name)
+                        && !tag[name.len()].is_ascii_alphanumeric()
+                    {
+                        // The end tag is scanned as text
+                        self.mode = Mode::Text;
+                        i += x;
+                    } else {
+                        i += x + 2;
+                    }
+                }
+            }
+        }
+        self.scanned = i;
+    }
+}
+
+// Fill slots with values got by name and filtered, append them to the result.
+// Errors come with where the slot begins, like `parse_slots`.
+pub fn fill_slots<'a, F>(
+    segments: &[Segment],
+    lookup: F,
+    result: &mut Html,
+) -> Result<(), (usize, String)>
+where
+    F: Fn(&str) -> Result<Value<'a>, String>,
+{
+    for segment in segments {
+        match segment {
+            Segment::Text(x) => result.push_str(x),
+            Segment::Slot(slot) => {
+                let invalid = |reason: String| (slot.offset, reason);
+                let mut value = lookup(&slot.name).map_err(invalid)?;
+                for filter in slot.filters.iter() {
+                    value = filter.apply(value).map_err(invalid)?;
+                }
+                let text = value.into_text();
+                let escaped = slot
+                    .filters
+                    .iter()
+                    .any(|x| matches!(x, Filter::Raw | Filter::Escape));
+                if escaped {
+                    result.push_str(&text);
+                } else {
+                    let text = result.escape(&text);
+                    result.push_str(&text);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod slot_tests {
+    use super::*;
+
+    fn fill(text: &str) -> Result<String, String> {
+        let segments = parse_slots(text).map_err(|(_, reason)| reason)?;
+        let mut result = Html::default();
+        fill_slots(
+            &segments,
+            |name| match name {
+                "blog.title" => Ok(Value::Text("Rust & <C++>".into())),
+                "blog.date" => Ok(Value::Date(2020, 3, 7)),
+                "blog.month" => Ok(Value::Text("3".into())),
+                "tag.name" => Ok(Value::Text("\"a\" < b".into())),
+                _ => Err(format!("unknown slot \"{}\".", name)),
+            },
+            &mut result,
+        )
+        .map_err(|(_, reason)| reason)?;
+        Ok(result.text)
+    }
+
+    #[test]
+    fn test_parse_slots() {
+        assert_eq!(
+            parse_slots("<a>{{ blog.date | date \"%Y\" | pad 6}}</a>_slot_of_tag_name\"").unwrap(),
+            vec![
+                Segment::Text("<a>".to_string()),
+                Segment::Slot(Slot {
+                    name: "blog.date".to_string(),
+                    filters: vec![Filter::Date("%Y".to_string()), Filter::Pad(6)],
+                    offset: 3,
+                }),
+                Segment::Text("</a>".to_string()),
+                Segment::Slot(Slot {
+                    name: "tag.name".to_string(),
+                    filters: Vec::new(),
+                    offset: 41,
+                }),
+                Segment::Text("\"".to_string()),
+            ]
+        );
+        assert_eq!(parse_slots("").unwrap(), vec![]);
+        assert!(parse_slots("{{ blog.title | lower }}").is_err());
+        assert!(parse_slots("{{ blog.title | truncate }}").is_err());
+        assert!(parse_slots("{{ blog.title | truncate ten }}").is_err());
+        assert!(parse_slots("{{ blog.date | date \"%Q\" }}").is_err());
+        assert!(parse_slots("{{ | escape }}").is_err());
+        assert!(parse_slots("{{ blog.title | escape | upper }}").is_err());
+        assert!(parse_slots("{{ blog.title | upper | escape }}").is_ok());
+        assert_eq!(parse_slots("<p>{{ blog.title | lower }}").unwrap_err().0, 3);
+    }
+
+    #[test]
+    fn test_fill_slots() {
+        assert_eq!(fill("{{blog.title|raw}}!").unwrap(), "Rust & <C++>!");
+        assert_eq!(
+            fill("{{blog.title|escape}}").unwrap(),
+            "Rust &amp; &lt;C++&gt;"
+        );
+        assert_eq!(
+            fill("{{ blog.title | upper | raw }}").unwrap(),
+            "RUST & <C++>"
+        );
+        assert_eq!(fill("{{ blog.title | truncate 7 }}").unwrap(), "Rust...");
+        assert_eq!(fill("{{ blog.title | truncate 2 }}").unwrap(), "Ru");
+        assert_eq!(
+            fill("{{ blog.title | upper | escape }}").unwrap(),
+            "RUST &amp; &lt;C++&gt;"
+        );
+        assert_eq!(
+            fill("{{ blog.title | truncate 40 | raw }}").unwrap(),
+            "Rust & <C++>"
+        );
+        assert_eq!(
+            fill("{{ blog.title | urlencode }}").unwrap(),
+            "Rust%20%26%20%3CC%2B%2B%3E"
+        );
+        assert_eq!(fill("{{ blog.month | pad }}").unwrap(), "03");
+        assert_eq!(fill("{{ blog.date }}").unwrap(), "2020/3/7");
+        assert_eq!(
+            fill("{{ blog.date | date \"%d %b %Y, %B %-d %-m%%\" }}").unwrap(),
+            "07 Mar 2020, March 7 3%"
+        );
+        assert!(fill("{{ blog.month | date }}").is_err());
+        assert!(fill("{{ blog.author }}").is_err());
+    }
+
+    #[test]
+    fn test_auto_escape() {
+        // Text
+        assert_eq!(
+            fill("<title>_slot_of_blog_title</title>").unwrap(),
+            "<title>Rust &amp; &lt;C++&gt;</title>"
+        );
+        assert_eq!(
+            fill("<p class=\"x\">'{{ blog.title }}'").unwrap(),
+            "<p class=\"x\">'Rust &amp; &lt;C++&gt;'"
+        );
+        // Attribute
+        assert_eq!(
+            fill("<a title=\"{{ blog.title | upper }}\">").unwrap(),
+            "<a title=\"RUST &amp; &lt;C++&gt;\">"
+        );
+        assert_eq!(
+            fill("<img alt='{{ blog.title }}' src=\"a.png\">").unwrap(),
+            "<img alt='Rust &amp; &lt;C++&gt;' src=\"a.png\">"
+        );
+        assert_eq!(
+            fill("<a\n  href=\"../{{ blog.title | urlencode }}\">").unwrap(),
+            "<a\n  href=\"../Rust%20%26%20%3CC%2B%2B%3E\">"
+        );
+        // Context is kept track of, not guessed from the last `<`
+        assert_eq!(
+            fill("<p>1 < 2, {{ tag.name }}</p>").unwrap(),
+            "<p>1 < 2, \"a\" &lt; b</p>"
+        );
+        assert_eq!(
+            fill("<a title=\"1 > 2\" data-x=\"{{ tag.name }}\">").unwrap(),
+            "<a title=\"1 > 2\" data-x=\"&quot;a&quot; &lt; b\">"
+        );
+        assert_eq!(
+            fill("<a title='a<b'>{{ tag.name }}</a>").unwrap(),
+            "<a title='a<b'>\"a\" &lt; b</a>"
+        );
+        assert_eq!(
+            fill("<script>if (a<b) x = \"{{ tag.name }}\";</script>").unwrap(),
+            "<script>if (a<b) x = \"\\u0022a\\u0022 \\u003C b\";</script>"
+        );
+        assert_eq!(
+            fill("<style>a::after { content: '{{ blog.title }}' }</style>").unwrap(),
+            "<style>a::after { content: 'Rust \\000026 \\00003CC\\00002B\\00002B\\00003E' }</style>"
+        );
+        assert_eq!(
+            fill("<SCRIPT>a</b;</Script ><i title={{ tag.name }}>").unwrap(),
+            "<SCRIPT>a</b;</Script ><i title=&quot;a&quot;&#32;&lt;&#32;b>"
+        );
+        assert_eq!(
+            fill("<!-- a<b {{ tag.name }} --><b title=\"{{ tag.name }}\">").unwrap(),
+            "<!-- a<b \"a\" &lt; b --><b title=\"&quot;a&quot; &lt; b\">"
+        );
+        assert_eq!(
+            fill("<!{{ tag.name }}<{{ tag.name }}").unwrap(),
+            "<!\"a\" &lt; b<\"a\" &lt; b"
+        );
+        // Escaped only once
+        assert_eq!(
+            fill("<a title=\"{{ blog.title | escape }}\">").unwrap(),
+            "<a title=\"Rust &amp; &lt;C++&gt;\">"
+        );
+        assert_eq!(
+            parse_slots("_slot_of_blog_content").unwrap(),
+            vec![Segment::Slot(Slot {
+                name: "blog.content".to_string(),
+                filters: vec![Filter::Raw],
+                offset: 0,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_html_context() {
+        // Quotes are escaped in tags only
+        let in_tag = |html: &mut Html| html.escape("\"") == "&quot;";
+        // Decided once more html comes
+        let mut html = Html::default();
+        html.push_str("<p>x<");
+        assert!(!in_tag(&mut html));
+        html.push_str("a href=\"");
+        assert!(in_tag(&mut html));
+        html.push_str("\"></a><!-");
+        assert!(!in_tag(&mut html));
+        html.push_str("- <a ");
+        assert!(!in_tag(&mut html));
+        html.push_str("-");
+        assert!(!in_tag(&mut html));
+        html.push_str("-><style>p<");
+        assert!(!in_tag(&mut html));
+        html.push_str("/sty");
+        assert!(!in_tag(&mut html));
+        html.push_str("le><b ");
+        assert!(in_tag(&mut html));
+    }
+}
@@ -6,9 +6,6 @@ pub struct Tag {
 
 impl Tag {
     pub fn new(name: String, description: String) -> Tag {
-        Tag {
-            name: name,
-            description: description,
-        }
+        Tag { name, description }
     }
 }
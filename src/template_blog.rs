@@ -1,30 +1,31 @@
 use comrak::{markdown_to_html, ComrakExtensionOptions, ComrakOptions, ComrakRenderOptions};
 use once_cell::sync::Lazy;
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::path::Path;
 use syntect::{
     easy::HighlightLines,
-    highlighting::ThemeSet,
-    html::{append_highlighted_html_for_styled_line, IncludeBackground},
-    parsing::SyntaxSet,
+    highlighting::{Theme, ThemeSet},
+    html::{
+        append_highlighted_html_for_styled_line, css_for_theme_with_class_style, ClassStyle,
+        ClassedHTMLGenerator, IncludeBackground,
+    },
+    parsing::{SyntaxReference, SyntaxSet},
     util::LinesWithEndings,
 };
 
-use crate::blog_clusters::BlogClusters;
-use crate::hlf_parser::{parse, HlfLhs, HlfRhs, Symbol};
-use crate::shared::path_title;
-use crate::shared::HTMLTemplate;
+use crate::blog::Blog;
+use crate::cache::{Cache, Hasher, Highlights};
+use crate::config::Highlight;
+use crate::error::{Error, Location, Reporter};
+use crate::expander::{Context, Expander, Schema, Scope};
+use crate::shared::{par_map, HTMLTemplate};
 
 // 1. Retrieves the blogs into cluster
 // 2. Parse the template file into HLF
 // 3. Use the information in cluster to expand the HLF to get the webpage result
-// For each kind of webpages the expand rules are different and hard-coded. The
-// hard-coded rules could be wrote in files, which make this program data driven
-// (But it's difficult and not practical because requirements are always ugly
-// and hard to be described in a general way).
-
-// Use a bnf-like thing is a fancier expression of html snippet provider
-// while symbol in content means this symbol can be repeated
+// The expand rules are written in the templates, symbols are bound to the
+// collections they repeat over, see `expander`.
 
 const LATEX_MARK: &[u8; 9] = b"lAtExhERE";
 const LATEX_MARK_LEN: usize = LATEX_MARK.len();
@@ -82,14 +83,13 @@ pub fn extract_latex(s: &str) -> (String, Vec<String>) {
      */
     for (i, &byte) in s.iter().enumerate() {
         match state {
-            0 => match byte {
-                b'$' => {
+            0 => {
+                if byte == b'$' {
                     state = 1;
                     new_s.extend(&s[ptr..i]);
                     ptr = i;
                 }
-                _ => (),
-            },
+            }
             1 => match byte {
                 b'$' => state = 3,
                 b'\r' | b'\n' => state = 0,
@@ -110,10 +110,11 @@ pub fn extract_latex(s: &str) -> (String, Vec<String>) {
                     _ => (),
                 }
             }
-            3 => match byte {
-                b'$' => state = 4,
-                _ => (),
-            },
+            3 => {
+                if byte == b'$' {
+                    state = 4;
+                }
+            }
             4 => match byte {
                 b'$' => {
                     state = 0;
@@ -142,14 +143,13 @@ pub fn extract_latex(s: &str) -> (String, Vec<String>) {
 
 // Replace marks in string given with latexes given. If latexes given more than
 // marks in string, this function returns None.
-pub fn insert_latex(s: &str, latexes: &Vec<String>) -> Option<String> {
+pub fn insert_latex(s: &str, latexes: &[String]) -> Option<String> {
     let s = s.as_bytes();
     let mut latexes_iter = 0;
     let mut begin = 0;
     let mut result = Vec::with_capacity(s.len());
     let mut i = 0;
-    let i_max = s.len() - LATEX_MARK_LEN;
-    while i < i_max {
+    while i + LATEX_MARK_LEN <= s.len() {
         if &s[i..i + LATEX_MARK_LEN] == LATEX_MARK {
             result.extend(&s[begin..i]);
             result.extend(LATEX_TAG_BEGIN);
@@ -181,22 +181,20 @@ pub fn html_unescape<T: AsRef<str>>(s: T) -> String {
         (0, Vec::with_capacity(s.len())),
         |(mut begin, mut result), i| {
             // unescape process
-            ESCAPE_TABLE
-                .iter()
-                .find_map(|(before, after)| {
-                    s.get(i..i + before.len()).and_then(|range| {
-                        if &range == before {
-                            Some((before.len(), after))
-                        } else {
-                            None
-                        }
-                    })
+            let found = ESCAPE_TABLE.iter().find_map(|(before, after)| {
+                s.get(i..i + before.len()).and_then(|range| {
+                    if &range == before {
+                        Some((before.len(), after))
+                    } else {
+                        None
+                    }
                 })
-                .map(|(offset, after)| {
-                    result.extend(&s[begin..i]);
-                    result.push(*after);
-                    begin = i + offset;
-                });
+            });
+            if let Some((offset, after)) = found {
+                result.extend(&s[begin..i]);
+                result.push(*after);
+                begin = i + offset;
+            }
             (begin, result)
         },
     );
@@ -212,9 +210,8 @@ pub fn html_escape<T: AsRef<str>>(s: T) -> String {
     let (begin, mut result) = s.iter().enumerate().fold(
         (0, Vec::with_capacity(s.len())),
         |(mut begin, mut result), (i, byte)| {
-            ESCAPE_TABLE
-                .iter()
-                .find_map(
+            let found =
+                ESCAPE_TABLE.iter().find_map(
                     |(before, after)| {
                         if byte == after {
                             Some(before)
@@ -222,12 +219,12 @@ pub fn html_escape<T: AsRef<str>>(s: T) -> String {
                             None
                         }
                     },
-                )
-                .map(|&before| {
-                    result.extend(&s[begin..i]);
-                    result.extend(before);
-                    begin = i + 1;
-                });
+                );
+            if let Some(&before) = found {
+                result.extend(&s[begin..i]);
+                result.extend(before);
+                begin = i + 1;
+            }
             (begin, result)
         },
     );
@@ -235,7 +232,8 @@ pub fn html_escape<T: AsRef<str>>(s: T) -> String {
     unsafe { String::from_utf8_unchecked(result) }
 }
 
-// Transform several frequently used markdown code annotation to file extension
+// Transform several frequently used markdown code annotation to file extension,
+// `[languages]` in the config goes first, see `find_syntax`
 pub fn lang2ext(lang: &str) -> &str {
     match lang {
         // Syntect have no ebnf syntax highlighting support :-/
@@ -247,166 +245,322 @@ pub fn lang2ext(lang: &str) -> &str {
     }
 }
 
-pub fn highlight_code(lang: &str, code: &str) -> String {
-    static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(|| SyntaxSet::load_defaults_newlines());
-    static THEME_SET: Lazy<ThemeSet> = Lazy::new(|| ThemeSet::load_defaults());
+// Highlighted code differs between them, see `Cache`. The version resolved is
+// set by `build.rs`.
+const SYNTECT_VERSION: &str = env!("SYNTECT_VERSION");
+
+// Prefixed, so they don't clash with classes of the site
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+// Loaded on the first use, which never comes if every code block is cached
+static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+static THEME_SET: Lazy<ThemeSet> = Lazy::new(ThemeSet::load_defaults);
+
+// Theme by name, the error lists the themes there are
+pub fn find_theme(name: &str) -> Result<&'static Theme, String> {
+    THEME_SET.themes.get(name).ok_or_else(|| {
+        let names: Vec<&str> = THEME_SET.themes.keys().map(|x| x.as_str()).collect();
+        format!(
+            "unknown theme \"{}\", themes are: {}.",
+            name,
+            names.join(", ")
+        )
+    })
+}
 
-    let syntax = SYNTAX_SET
-        .find_syntax_by_extension(lang2ext(lang))
-        .expect(&format!("Unknown language: {}!", lang));
-    let ref theme = THEME_SET.themes["base16-ocean.light"];
-    let mut highlighter = HighlightLines::new(syntax, theme);
+// Stylesheet for code highlighted with classes. With a dark theme, it's used
+// when the reader prefers a dark color scheme.
+pub fn highlight_css(highlight: &Highlight) -> Result<String, String> {
+    let css = |theme| {
+        css_for_theme_with_class_style(find_theme(theme)?, CLASS_STYLE)
+            .map_err(|err| format!("can't generate the stylesheet: {}", err))
+    };
+    let mut result = css(&highlight.theme)?;
+    if let Some(dark_theme) = &highlight.dark_theme {
+        result.push_str("\n@media (prefers-color-scheme: dark) {\n");
+        for line in css(dark_theme)?.lines() {
+            if !line.is_empty() {
+                result.push_str("  ");
+                result.push_str(line);
+            }
+            result.push('\n');
+        }
+        result.push_str("}\n");
+    }
+    Ok(result)
+}
+
+// Syntax by extension, then by name, then by the first line of the code.
+// `languages` maps languages to extensions before the builtin `lang2ext`.
+fn find_syntax(
+    lang: &str,
+    code: &str,
+    languages: &BTreeMap<String, String>,
+) -> Option<&'static SyntaxReference> {
+    let ext = languages
+        .get(lang)
+        .map_or_else(|| lang2ext(lang), |x| x.as_str());
+    SYNTAX_SET
+        .find_syntax_by_extension(ext)
+        // Extension again, then case insensitive name
+        .or_else(|| SYNTAX_SET.find_syntax_by_token(lang))
+        .or_else(|| SYNTAX_SET.find_syntax_by_first_line(code))
+}
 
-    let code_unesc = html_unescape(&code);
+// Spans with inline styles of the theme, or with class names. None if no
+// syntax is found for the language.
+pub fn highlight_code(
+    lang: &str,
+    code: &str,
+    highlight: &Highlight,
+) -> Result<Option<String>, String> {
+    let code_unesc = html_unescape(code);
+    let Some(syntax) = find_syntax(lang, &code_unesc, &highlight.languages) else {
+        return Ok(None);
+    };
+    let failed = |err: syntect::Error| format!("Code highlighting failed: {}", err);
+
+    if highlight.classes {
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, CLASS_STYLE);
+        for line in LinesWithEndings::from(&code_unesc) {
+            generator
+                .parse_html_for_line_which_includes_newline(line)
+                .map_err(failed)?;
+        }
+        return Ok(Some(generator.finalize()));
+    }
+
+    let theme = find_theme(&highlight.theme)?;
+    let mut highlighter = HighlightLines::new(syntax, theme);
     let mut code_highlight = String::with_capacity(code_unesc.len() * 2);
 
     for line in LinesWithEndings::from(&code_unesc) {
-        let regions = highlighter.highlight(line, &SYNTAX_SET);
+        let regions = highlighter
+            .highlight_line(line, &SYNTAX_SET)
+            .map_err(failed)?;
         append_highlighted_html_for_styled_line(
             &regions,
             IncludeBackground::No,
             &mut code_highlight,
-        );
+        )
+        .map_err(failed)?;
     }
 
-    code_highlight
+    Ok(Some(code_highlight))
 }
 
-pub struct BlogTemplate {
-    hlfs: HashMap<HlfLhs, HlfRhs>,
+// Settings changing highlighted html, classes are the same for every theme
+fn hash_highlight(hasher: &mut Hasher, highlight: &Highlight) {
+    let (style, theme) = match highlight.classes {
+        true => ("classes", ""),
+        false => ("inline", highlight.theme.as_str()),
+    };
+    for x in [SYNTECT_VERSION, style, theme] {
+        hasher.write(x.as_bytes());
+    }
 }
 
-impl HTMLTemplate for BlogTemplate {
-    fn load(template_raw: &str) -> Result<Self, String> {
-        let hlfs_vec = match parse(&template_raw) {
+fn highlight_cached(
+    highlights: &Highlights,
+    lang: &str,
+    code: &str,
+    highlight: &Highlight,
+) -> Result<Option<String>, String> {
+    let ext = highlight.languages.get(lang).map_or("", |x| x.as_str());
+    let mut hasher = Hasher::new();
+    hash_highlight(&mut hasher, highlight);
+    for x in [lang, ext, code] {
+        hasher.write(x.as_bytes());
+    }
+    highlights.get_or(hasher.finish(), || highlight_code(lang, code, highlight))
+}
+
+// Key of the rendered content of a blog in the cache. Any code block could be
+// in a language aliased, so all aliases count.
+pub fn content_key(blog: &Blog, highlight: &Highlight) -> u64 {
+    let mut hasher = Hasher::new();
+    hash_highlight(&mut hasher, highlight);
+    for (lang, ext) in highlight.languages.iter() {
+        hasher.write(lang.as_bytes());
+        hasher.write(ext.as_bytes());
+    }
+    hasher.write(blog.content.as_bytes());
+    hasher.finish()
+}
+
+// Markdown of the blog content to html, with LaTeX kept and code highlighted.
+// Languages of code blocks left plain are returned too.
+fn render_content(
+    blog: &Blog,
+    highlights: &Highlights,
+    highlight: &Highlight,
+) -> Result<(String, Vec<String>), String> {
+    // 1. Markdown to html
+    // 2. Retrieve code blocks in html.
+    // 3. Do syntax highlighting on unescaped code blocks
+    //    according to code annotation. (code may contains
+    //    some characters will be escaped to fit into html)
+
+    // This solution is inspired by author of comrak:
+    // https://github.com/kivikakk/comrak/issues/129. But
+    // actually a better solution is extracting code blocks
+    // before converting markdown to html and insert the
+    // highlighted code after it. This is how we process latex
+    // blocks, but I come up with it before I finish the code
+    // highlighting part :-P. It works anyway....
+
+    let options = ComrakOptions {
+        // Enable frequently used github markdown extensions
+        extension: ComrakExtensionOptions {
+            tasklist: true,
+            table: true,
+            strikethrough: true,
+            ..Default::default()
+        },
+        render: ComrakRenderOptions {
+            github_pre_lang: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (content, latexes) = extract_latex(&blog.content);
+    let content = markdown_to_html(&content, &options);
+    let raw_html = insert_latex(&content, &latexes).ok_or("LaTeX insertion error!")?;
+    // Assume latex never overlaps with or contained by code.
+    static RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"<pre lang="([^"]*)"><code>([^<]*)</code></pre>"#).unwrap());
+    let mut result = String::new();
+    let mut unknown_langs = Vec::new();
+    let mut begin = 0;
+    for cap in RE.captures_iter(&raw_html) {
+        let lang = cap.get(1).unwrap().as_str();
+        let code = cap.get(2).unwrap().as_str();
+        // Escaped already
+        let code_highlight = &match highlight_cached(highlights, lang, code, highlight)? {
             Some(x) => x,
-            None => return Err("template parse failed".to_string()),
+            None => {
+                if !unknown_langs.iter().any(|x| x == lang) {
+                    unknown_langs.push(lang.to_string());
+                }
+                code.to_string()
+            }
         };
-        let mut hlfs = HashMap::new();
-        for i in hlfs_vec.iter() {
-            hlfs.insert(i.lhs.clone(), i.rhs.clone());
-        }
-        Ok(Self { hlfs: hlfs })
+        let range = cap.get(0).unwrap().range();
+        let end = range.start;
+        result.push_str(&raw_html[begin..end]);
+        result.push_str(r#"<pre lang=""#);
+        result.push_str(lang);
+        result.push_str(r#""><code>"#);
+        result.push_str(code_highlight);
+        result.push_str(r#"</code></pre>"#);
+        begin = range.end;
     }
+    result.push_str(&raw_html[begin..]);
+    Ok((result, unknown_langs))
+}
 
-    fn fill(&self, cluster: &BlogClusters) -> Vec<(String, String)> {
-        let mut results = Vec::new();
+// Expanded per blog, with its rendered content
+const SCHEMA: Schema = Schema {
+    symbols: &["main"],
+    collections: &[],
+    scope: Scope {
+        blog: true,
+        tag: false,
+        content: true,
+    },
+};
 
-        // We have the knowledge of blog template's structure
-        let main_rhs = self
-            .hlfs
-            .get("main")
-            .expect("there should be a main symbol in blog template.");
-        let tags_rhs = match main_rhs.get(1).unwrap() {
-            Symbol::N(x) => self
-                .hlfs
-                .get(x)
-                .expect(&format!("\"{}\" symbol not found.", x)),
-            _ => panic!(),
-        };
-        let tag_rhs = match tags_rhs.get(1).unwrap() {
-            Symbol::N(x) => self
-                .hlfs
-                .get(x)
-                .expect(&format!("\"{}\" symbol not found.", x)),
-            _ => panic!(),
-        };
-        assert_eq!(main_rhs.len(), 3);
-        assert_eq!(tags_rhs.len(), 3);
-        assert_eq!(tag_rhs.len(), 1);
-
-        let blogs = cluster.get_blogs();
-        for blog in blogs {
-            let mut result = String::new();
-            match main_rhs.get(0).unwrap() {
-                Symbol::T(x) => {
-                    // 1. Markdown to html
-                    // 2. Retrieve code blocks in html.
-                    // 3. Do syntax highlighting on unescaped code blocks
-                    //    according to code annotation. (code may contains
-                    //    some characters will be escaped to fit into html)
-
-                    // This solution is inspired by author of comrak:
-                    // https://github.com/kivikakk/comrak/issues/129. But
-                    // actually a better solution is extracting code blocks
-                    // before converting markdown to html and insert the
-                    // highlighted code after it. This is how we process latex
-                    // blocks, but I come up with it before I finish the code
-                    // highlighting part :-P. It works anyway....
-
-                    let options = ComrakOptions {
-                        // Enable frequently used github markdown extensions
-                        extension: ComrakExtensionOptions {
-                            tasklist: true,
-                            table: true,
-                            strikethrough: true,
-                            ..Default::default()
-                        },
-                        render: ComrakRenderOptions {
-                            github_pre_lang: true,
-                            ..Default::default()
-                        },
-                        ..Default::default()
-                    };
-                    let (content, latexes) = extract_latex(&blog.content);
-                    let content = markdown_to_html(&content, &options);
-                    let raw_html = x
-                        .replace("_slot_of_blog_title", &blog.title)
-                        .replace("_slot_of_blog_day", &blog.day.to_string())
-                        .replace("_slot_of_blog_month", &blog.month.to_string())
-                        .replace("_slot_of_blog_year", &blog.year.to_string())
-                        .replace("_slot_of_blog_preview", &blog.preview)
-                        .replace("_slot_of_blog_content", &content);
-                    let raw_html = match insert_latex(&raw_html, &latexes) {
-                        Some(x) => x,
-                        None => panic!("LaTeX insertion error!"),
-                    };
-                    // Assume latex never overlaps with or contained by code.
-                    static RE: Lazy<Regex> = Lazy::new(|| {
-                        Regex::new(r#"<pre lang="([^"]*)"><code>([^<]*)</code></pre>"#).unwrap()
-                    });
-                    let mut begin = 0;
-                    for cap in RE.captures_iter(&raw_html) {
-                        let lang = cap.get(1).unwrap().as_str();
-                        let code = cap.get(2).unwrap().as_str();
-                        let ref code_highlight = highlight_code(lang, code);
-                        let range = cap.get(0).unwrap().range();
-                        let end = range.start;
-                        result.push_str(&raw_html[begin..end]);
-                        result.push_str(r#"<pre lang=""#);
-                        result.push_str(lang);
-                        result.push_str(r#""><code>"#);
-                        result.push_str(code_highlight);
-                        result.push_str(r#"</code></pre>"#);
-                        begin = range.end;
+pub struct BlogTemplate {
+    expander: Expander,
+}
+
+impl HTMLTemplate for BlogTemplate {
+    fn load(template_path: &Path, template_raw: &str) -> Result<Self, Error> {
+        Ok(Self {
+            expander: Expander::load_for(template_path, template_raw, &SCHEMA)?,
+        })
+    }
+
+    // One page per blog, `blog.tags` lists tags of the blog
+    fn fill(&self, context: Context, reporter: &mut Reporter) -> Vec<(String, String)> {
+        let highlight = Highlight::default();
+        self.fill_with(
+            context,
+            |_| true,
+            &mut Cache::default(),
+            0,
+            &highlight,
+            reporter,
+        )
+    }
+}
+
+impl BlogTemplate {
+    // Pages of the blogs `wanted`. Contents not in the cache are rendered on
+    // `jobs` threads, pages are in the order of blogs anyway.
+    pub fn fill_with(
+        &self,
+        context: Context,
+        wanted: impl Fn(&Blog) -> bool,
+        cache: &mut Cache,
+        jobs: usize,
+        highlight: &Highlight,
+        reporter: &mut Reporter,
+    ) -> Vec<(String, String)> {
+        let blogs: Vec<&Blog> = context
+            .clusters
+            .get_blogs()
+            .iter()
+            .filter(|x| wanted(x))
+            .collect();
+        let keys: Vec<u64> = blogs.iter().map(|x| content_key(x, highlight)).collect();
+        let cached: Vec<Option<String>> = keys.iter().map(|x| cache.get(*x)).collect();
+        let misses: Vec<&Blog> = blogs
+            .iter()
+            .zip(cached.iter())
+            .filter(|(_, x)| x.is_none())
+            .map(|(blog, _)| *blog)
+            .collect();
+        let mut rendered = par_map(&misses, jobs, |x| {
+            render_content(x, &cache.highlights, highlight)
+        })
+        .into_iter();
+
+        let mut results = Vec::new();
+        for ((blog, cached), key) in blogs.into_iter().zip(cached).zip(keys) {
+            let content = match cached {
+                Some(x) => Ok(x),
+                // Not cached with warnings, so they show up in every build
+                None => rendered.next().unwrap().map(|(content, unknown_langs)| {
+                    if unknown_langs.is_empty() {
+                        cache.insert(key, content.clone());
                     }
-                    result.push_str(&raw_html[begin..]);
-                }
-                _ => panic!(),
-            };
-            match tags_rhs.get(0).unwrap() {
-                Symbol::T(x) => result.push_str(x),
-                _ => panic!(),
-            };
-            // add multiple tag names
-            for tag_handle in blog.tags.iter() {
-                match tag_rhs.get(0).unwrap() {
-                    Symbol::T(x) => {
-                        let tag = cluster.get_tag(*tag_handle).unwrap();
-                        result.push_str(&x.replace("_slot_of_tag_name", &tag.name));
+                    for lang in unknown_langs {
+                        reporter.warn(Error::UnknownLanguage(Location::file(&blog.source), lang));
                     }
-                    _ => panic!(),
-                };
-            }
-            match tags_rhs.get(2).unwrap() {
-                Symbol::T(x) => result.push_str(x),
-                _ => panic!(),
+                    content
+                }),
+            };
+            let content = match content {
+                Ok(x) => x,
+                Err(reason) => {
+                    // Other blogs are still rendered to find more problems
+                    reporter.report(Error::Render(Location::file(&blog.source), reason));
+                    continue;
+                }
             };
-            match main_rhs.get(2).unwrap() {
-                Symbol::T(x) => result.push_str(x),
-                _ => panic!(),
+            match self
+                .expander
+                .expand(context.with_blog(blog).with_content(&content))
+            {
+                Ok(result) => results.push((blog.path(), result)),
+                Err(err) => {
+                    // Template is broken, the other blogs won't be better
+                    reporter.report(err);
+                    return Vec::new();
+                }
             }
-            results.push((format!("{}{}", &path_title(&blog.title), ".html"), result));
         }
         results
     }
@@ -415,6 +569,136 @@ impl HTMLTemplate for BlogTemplate {
 #[cfg(test)]
 mod template_tests {
     use super::*;
+    use crate::blog_clusters::BlogClusters;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_highlight_classes() {
+        let mut highlight = Highlight::default();
+        let inline = highlight_code("rust", "fn main() {}\n", &highlight)
+            .unwrap()
+            .unwrap();
+        assert!(inline.contains("style=\"color:"));
+        highlight.classes = true;
+        let classes = highlight_code("rust", "fn main() {}\n", &highlight)
+            .unwrap()
+            .unwrap();
+        assert!(classes.contains("<span class=\"hl-source hl-rust\">"));
+        assert!(!classes.contains("style="));
+
+        highlight.theme = "base16-ocean.dark".to_string();
+        let css = highlight_css(&highlight).unwrap();
+        assert!(css.contains(".hl-code {"));
+        assert!(!css.contains("@media"));
+        highlight.theme = "nope".to_string();
+        let err = highlight_css(&highlight).unwrap_err();
+        assert!(err.starts_with("unknown theme \"nope\", themes are: "));
+        assert!(err.contains("base16-ocean.light"));
+    }
+
+    #[test]
+    fn test_highlight_fallback() {
+        let mut highlight = Highlight::default();
+        let highlighted = |lang, code, highlight: &Highlight| {
+            highlight_code(lang, code, highlight).unwrap().is_some()
+        };
+        // By extension, name, then the first line
+        assert!(highlighted("c++", "int x;\n", &highlight));
+        assert!(highlighted("RUST", "fn main() {}\n", &highlight));
+        assert!(highlighted("nope", "#!/bin/bash\necho hi\n", &highlight));
+        assert!(!highlighted("mermaid", "graph TD\n", &highlight));
+        highlight
+            .languages
+            .insert("mermaid".to_string(), "txt".to_string());
+        assert!(highlighted("mermaid", "graph TD\n", &highlight));
+    }
+
+    #[test]
+    fn test_render_unknown_language() {
+        let content = "```tmol\na = \"<b>\"\n```\n\n```tmol\n```\n\n```rust\nfn main() {}\n```\n";
+        let mut blog = Blog::new(
+            2021,
+            4,
+            2,
+            "a".to_string(),
+            Vec::new(),
+            String::new(),
+            content.to_string(),
+        )
+        .unwrap();
+        blog.source = PathBuf::from("blogs/a.md");
+        let (html, unknown_langs) =
+            render_content(&blog, &Highlights::default(), &Highlight::default()).unwrap();
+        assert_eq!(unknown_langs, vec!["tmol".to_string()]);
+        assert!(html.contains("<pre lang=\"tmol\"><code>a = &quot;&lt;b&gt;&quot;\n</code></pre>"));
+        assert!(html.contains("<pre lang=\"rust\"><code><span style="));
+    }
+
+    #[test]
+    fn test_cached_content_follows_highlight() {
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_blogs(
+            &[(
+                PathBuf::from("a.md"),
+                "+++\ntitle = \"a\"\ndate = 2021-04-02\ntags = []\n+++\npreview\n---\n```rust\nfn main() {}\n```\n"
+                    .to_string(),
+            )],
+            &mut reporter,
+        );
+        let template = BlogTemplate::load(
+            Path::new("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content-->{{ blog.content | raw }}<!--content-->",
+        )
+        .unwrap();
+        let mut cache = Cache::default();
+        let mut fill = |highlight: &Highlight| {
+            let pages = template.fill_with(
+                Context::new(&clusters),
+                |_| true,
+                &mut cache,
+                1,
+                highlight,
+                &mut reporter,
+            );
+            pages[0].1.clone()
+        };
+        let mut highlight = Highlight::default();
+        let inline = fill(&highlight);
+        assert!(inline.contains("style=\"color:"));
+        highlight.classes = true;
+        let classes = fill(&highlight);
+        assert!(classes.contains("<span class=\"hl-source hl-rust\">"));
+        highlight.classes = false;
+        highlight.theme = "InspiredGitHub".to_string();
+        let themed = fill(&highlight);
+        assert!(themed.contains("style=\"color:"));
+        assert_ne!(inline, themed);
+        highlight
+            .languages
+            .insert("rust".to_string(), "txt".to_string());
+        let aliased = fill(&highlight);
+        assert_ne!(themed, aliased);
+        assert!(!reporter.has_errors());
+    }
+
+    #[test]
+    fn test_highlight_css_dark() {
+        let highlight = Highlight {
+            classes: true,
+            theme: "InspiredGitHub".to_string(),
+            dark_theme: Some("base16-ocean.dark".to_string()),
+            ..Default::default()
+        };
+        let css = highlight_css(&highlight).unwrap();
+        let (light, dark) = css
+            .split_once("@media (prefers-color-scheme: dark) {\n")
+            .unwrap();
+        assert!(light.contains("theme \"GitHub\""));
+        assert!(dark.contains("  .hl-code {\n"));
+        assert!(dark.contains("theme \"Base16 Ocean Dark\""));
+        assert!(dark.ends_with("}\n}\n"));
+    }
 
     #[test]
     fn test_html_unescape() {
@@ -463,7 +747,7 @@ mod template_tests {
         vcxsrtyjkghmnabsdjf])))
         "#;
 
-        assert_eq!(html_unescape(&html_escape(&chaos)), chaos);
+        assert_eq!(html_unescape(html_escape(chaos)), chaos);
     }
 
     #[test]
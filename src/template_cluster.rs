@@ -1,31 +1,90 @@
-use std::collections::HashMap;
+use std::path::Path;
 
-use crate::blog_clusters::BlogClusters;
-use crate::hlf_parser::{parse, HlfLhs, HlfRhs, Symbol};
+use crate::error::{Error, Reporter};
+use crate::expander::{Collection, Context, Expander, Schema, Scope};
 use crate::shared::HTMLTemplate;
 
+// Lists all tags
+const SCHEMA: Schema = Schema {
+    symbols: &["main"],
+    collections: &[Collection::Tags],
+    scope: Scope {
+        blog: false,
+        tag: false,
+        content: false,
+    },
+};
+
 pub struct ClusterTemplate {
-    hlfs: HashMap<HlfLhs, HlfRhs>,
+    expander: Expander,
 }
 
 impl HTMLTemplate for ClusterTemplate {
-    fn load(template_raw: &str) -> Result<Self, String> {
-        let hlfs_vec = match parse(&template_raw) {
-            Some(x) => x,
-            None => return Err("template parse failed".to_string()),
-        };
-        let mut hlfs = HashMap::new();
-        for i in hlfs_vec.iter() {
-            hlfs.insert(i.lhs.clone(), i.rhs.clone());
+    fn load(template_path: &Path, template_raw: &str) -> Result<Self, Error> {
+        Ok(Self {
+            expander: Expander::load_for(template_path, template_raw, &SCHEMA)?,
+        })
+    }
+
+    fn fill(&self, context: Context, reporter: &mut Reporter) -> Vec<(String, String)> {
+        match self.expander.expand(context) {
+            Ok(result) => vec![("cluster.html".to_string(), result)],
+            Err(err) => {
+                reporter.report(err);
+                Vec::new()
+            }
         }
-        Ok(Self { hlfs: hlfs })
     }
-    fn fill(&self, clusters: &BlogClusters) -> Vec<(String, String)> {
-        let mut content = String::new();
-        //let
-        //for i in
-        // maybe shouldn't use replace because it's variable length
-        // tmp = self.raw.replace("_slot_of_tags", );
-        vec![("clusters.html".to_string(), content)]
+}
+
+#[cfg(test)]
+mod template_cluster_tests {
+    use super::*;
+    use crate::blog_clusters::BlogClusters;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_cluster_fill() {
+        let template: ClusterTemplate = HTMLTemplate::load(
+            Path::new("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content-->[<!--symbol-->cluster<!--symbol-->]<!--content-->
+            <!--symbol-->cluster : tags<!--symbol--><!--content-->_slot_of_tag_name(<!--symbol-->blogs<!--symbol-->)<!--content-->
+            <!--symbol-->blogs<!--symbol--><!--content-->:<!--symbol-->blog<!--symbol-->;<!--content-->
+            <!--symbol-->blog : tag.blogs<!--symbol--><!--content-->_slot_of_blog_path <!--content-->",
+        )
+        .unwrap();
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_tags(
+            Path::new("tags.txt"),
+            "life
+            things about current life
+
+            work
+            about my works",
+            &mut reporter,
+        );
+        clusters.add_blogs(
+            &[
+                (
+                    PathBuf::from("old-blog.md"),
+                    "Old Blog\n2000/9/27\nlife | work\n---\npreview\n---\ncontent".to_string(),
+                ),
+                (
+                    PathBuf::from("new-blog.md"),
+                    "New Blog\n2001/9/27\nlife\n---\npreview\n---\ncontent".to_string(),
+                ),
+            ],
+            &mut reporter,
+        );
+        assert!(!reporter.has_errors());
+        let result = template.fill(Context::new(&clusters), &mut reporter);
+        assert_eq!(
+            result,
+            vec![(
+                "cluster.html".to_string(),
+                "[life(:new-blog.html old-blog.html ;)work(:old-blog.html ;)]".to_string()
+            )]
+        );
     }
 }
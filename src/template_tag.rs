@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use crate::error::{Error, Reporter};
+use crate::expander::{Collection, Context, Expander, Schema, Scope};
+use crate::shared::{tag_path, HTMLTemplate};
+
+// Expanded per tag, lists blogs of it
+const SCHEMA: Schema = Schema {
+    symbols: &["main"],
+    collections: &[Collection::TagBlogs],
+    scope: Scope {
+        blog: false,
+        tag: true,
+        content: false,
+    },
+};
+
+pub struct TagTemplate {
+    expander: Expander,
+}
+
+impl HTMLTemplate for TagTemplate {
+    fn load(template_path: &Path, template_raw: &str) -> Result<Self, Error> {
+        Ok(Self {
+            expander: Expander::load_for(template_path, template_raw, &SCHEMA)?,
+        })
+    }
+
+    // One page per tag, `tag.blogs` lists blogs carrying the tag
+    fn fill(&self, context: Context, reporter: &mut Reporter) -> Vec<(String, String)> {
+        let mut results = Vec::new();
+        for tag in context.clusters.get_tags() {
+            match self.expander.expand(context.with_tag(tag).with_root("../")) {
+                Ok(result) => results.push((tag_path(&tag.name), result)),
+                Err(err) => {
+                    // Template is broken, the other tags won't be better
+                    reporter.report(err);
+                    return Vec::new();
+                }
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod template_tag_tests {
+    use super::*;
+    use crate::blog_clusters::BlogClusters;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_tag_fill() {
+        let template: TagTemplate = HTMLTemplate::load(
+            Path::new("template.html"),
+            "<!--symbol-->main<!--symbol--><!--content-->_slot_of_tag_name:<!--symbol-->blog<!--symbol-->;_slot_of_tag_description<!--content-->
+            <!--symbol-->blog : tag.blogs<!--symbol--><!--content-->_slot_of_blog_path <!--content-->",
+        )
+        .unwrap();
+        let mut reporter = Reporter::new();
+        let mut clusters = BlogClusters::new();
+        clusters.add_tags(
+            Path::new("tags.txt"),
+            "Life
+            things about current life
+
+            Machine Learning
+            magic",
+            &mut reporter,
+        );
+        clusters.add_blogs(
+            &[
+                (
+                    PathBuf::from("old-blog.md"),
+                    "Old Blog\n2000/9/27\nLife | Machine Learning\n---\npreview\n---\ncontent"
+                        .to_string(),
+                ),
+                (
+                    PathBuf::from("new-blog.md"),
+                    "New Blog\n2001/9/27\nLife\n---\npreview\n---\ncontent".to_string(),
+                ),
+            ],
+            &mut reporter,
+        );
+        assert!(!reporter.has_errors());
+        let result = template.fill(Context::new(&clusters), &mut reporter);
+        assert_eq!(
+            result,
+            vec![
+                (
+                    "tags/life.html".to_string(),
+                    "Life:new-blog.html old-blog.html ;things about current life".to_string()
+                ),
+                (
+                    "tags/machine-learning.html".to_string(),
+                    "Machine Learning:old-blog.html ;magic".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Watch files for changes.
+//!
+//! No notification API is portable in std, so files are polled: modification
+//! time and length of every file under the roots are compared with the last
+//! snapshot. Blog directories are small, polling them is cheap enough.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.added
+            .iter()
+            .chain(self.modified.iter())
+            .chain(self.removed.iter())
+    }
+
+    fn merge(&mut self, other: Changes) {
+        for (paths, others) in [
+            (&mut self.added, other.added),
+            (&mut self.modified, other.modified),
+            (&mut self.removed, other.removed),
+        ] {
+            for path in others {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+    }
+}
+
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+// Files under `path`, or itself if it's a file. Missing ones are skipped, they
+// show up as added once created.
+fn walk(path: &Path, snapshot: &mut Snapshot) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    if metadata.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            walk(&entry.path(), snapshot);
+        }
+    } else {
+        snapshot.insert(
+            path.to_path_buf(),
+            (metadata.modified().ok(), metadata.len()),
+        );
+    }
+}
+
+pub struct Watcher {
+    roots: Vec<PathBuf>,
+    snapshot: Snapshot,
+}
+
+impl Watcher {
+    // Files or directories to watch, changes are since now
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        let mut watcher = Watcher {
+            roots,
+            snapshot: Snapshot::new(),
+        };
+        watcher.snapshot = watcher.take();
+        watcher
+    }
+
+    // Roots could change after a rebuild, e.g. a template includes another
+    // partial. Roots still watched keep their snapshot, so changes during the
+    // rebuild are reported by the next poll, new roots are watched from now.
+    pub fn set_roots(&mut self, roots: Vec<PathBuf>) {
+        let mut snapshot = Snapshot::new();
+        for root in roots.iter() {
+            if self.roots.contains(root) {
+                let kept = self.snapshot.iter().filter(|(x, _)| x.starts_with(root));
+                snapshot.extend(kept.map(|(x, stamp)| (x.clone(), *stamp)));
+            } else {
+                walk(root, &mut snapshot);
+            }
+        }
+        self.roots = roots;
+        self.snapshot = snapshot;
+    }
+
+    fn take(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        for root in self.roots.iter() {
+            walk(root, &mut snapshot);
+        }
+        snapshot
+    }
+
+    // Changes since the last poll
+    pub fn poll(&mut self) -> Changes {
+        let snapshot = self.take();
+        let mut changes = Changes::default();
+        for (path, stamp) in snapshot.iter() {
+            match self.snapshot.get(path) {
+                None => changes.added.push(path.clone()),
+                Some(x) if x != stamp => changes.modified.push(path.clone()),
+                _ => (),
+            }
+        }
+        for path in self.snapshot.keys() {
+            if !snapshot.contains_key(path) {
+                changes.removed.push(path.clone());
+            }
+        }
+        self.snapshot = snapshot;
+        changes
+    }
+
+    // Block until something changes. Editors often write a file in several
+    // steps, so wait till nothing changes for an `interval`.
+    pub fn wait(&mut self, interval: Duration) -> Changes {
+        loop {
+            thread::sleep(interval);
+            let mut changes = self.poll();
+            if changes.is_empty() {
+                continue;
+            }
+            loop {
+                thread::sleep(interval);
+                let more = self.poll();
+                if more.is_empty() {
+                    return changes;
+                }
+                changes.merge(more);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+
+    #[test]
+    fn test_poll() {
+        let dir = std::env::temp_dir().join(format!("blogen-watch-{}", std::process::id()));
+        fs::create_dir_all(dir.join("blogs")).unwrap();
+        fs::write(dir.join("blogs/a.md"), "a").unwrap();
+        fs::write(dir.join("tags.txt"), "x").unwrap();
+
+        let mut watcher = Watcher::new(vec![
+            dir.join("blogs"),
+            dir.join("tags.txt"),
+            dir.join("missing.html"),
+        ]);
+        assert!(watcher.poll().is_empty());
+
+        // Length changes even if the modification time is too coarse
+        fs::write(dir.join("blogs/a.md"), "aa").unwrap();
+        fs::create_dir_all(dir.join("blogs/drafts")).unwrap();
+        fs::write(dir.join("blogs/drafts/b.md"), "b").unwrap();
+        fs::remove_file(dir.join("tags.txt")).unwrap();
+        assert_eq!(
+            watcher.poll(),
+            Changes {
+                added: vec![dir.join("blogs/drafts/b.md")],
+                modified: vec![dir.join("blogs/a.md")],
+                removed: vec![dir.join("tags.txt")],
+            }
+        );
+        assert!(watcher.poll().is_empty());
+
+        fs::write(dir.join("missing.html"), "").unwrap();
+        assert_eq!(watcher.poll().added, vec![dir.join("missing.html")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_set_roots() {
+        let dir = std::env::temp_dir().join(format!("blogen-roots-{}", std::process::id()));
+        fs::create_dir_all(dir.join("blogs")).unwrap();
+        fs::write(dir.join("blogs/a.md"), "a").unwrap();
+        fs::write(dir.join("layout.html"), "x").unwrap();
+        fs::write(dir.join("widgets.html"), "w").unwrap();
+
+        let mut watcher = Watcher::new(vec![dir.join("blogs"), dir.join("layout.html")]);
+        assert!(watcher.poll().is_empty());
+        // Saved during a rebuild
+        fs::write(dir.join("blogs/a.md"), "aa").unwrap();
+        fs::write(dir.join("layout.html"), "xx").unwrap();
+        watcher.set_roots(vec![dir.join("blogs"), dir.join("widgets.html")]);
+        assert_eq!(
+            watcher.poll(),
+            Changes {
+                modified: vec![dir.join("blogs/a.md")],
+                ..Default::default()
+            }
+        );
+        fs::write(dir.join("widgets.html"), "ww").unwrap();
+        assert_eq!(watcher.poll().modified, vec![dir.join("widgets.html")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}